@@ -1,8 +1,8 @@
-use crate::tag_memory::TagMemory;
 use crate::tag_sensors::adxl363 as adxl;
 use crate::tag_sensors::*;
 use libstuhfl::gen2::*;
 use libstuhfl::prelude::*;
+use libstuhfl::error::Error as StuhflError;
 use serial_test::serial;
 use chrono::Local;
 use std::error::Error;
@@ -10,16 +10,4226 @@ use std::fs::OpenOptions;
 use std::io;
 use std::io::Write;
 type TestResult = Result<(), libstuhfl::error::Error>;
+type Gen2Result<T> = Result<T, libstuhfl::error::Error>;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use ctrlc;
 
 
+/// One temp_log row: a tag's own reading, or why it has none.
 struct TemperatureData {
+    epc: HexID,
+    tid: HexID,
     timestamp: String,
+    temperature: Result<f32, String>,
+}
+
+impl TemperatureData {
+    fn csv_row(&self) -> String {
+        match &self.temperature {
+            Ok(temperature) => format!("{}, {}, {}, {temperature}, ok", self.epc, self.tid, self.timestamp),
+            Err(reason) => format!("{}, {}, {}, , failed ({reason})", self.epc, self.tid, self.timestamp),
+        }
+    }
+}
+
+/// The reader operations the tag procedures in this file rely on. `Gen2Reader`
+/// implements it by forwarding to libstuhfl; anything else implementing it can
+/// stand in for the ST25RU3993 when no board is attached.
+trait Gen2Transport {
+    fn inventory_once(&mut self) -> Gen2Result<(InventoryStatistics, Vec<InventoryTag>)>;
+
+    fn inventory(
+        &mut self,
+        rounds: u32,
+        callback: Box<dyn Fn(InventoryTag)>,
+    ) -> Gen2Result<InventoryStatistics>;
+
+    fn select(&mut self, epc: &HexID) -> Gen2Result<()>;
+
+    fn read_alt(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        num_words: u8,
+        password: Option<Password>,
+    ) -> Gen2Result<Vec<u8>>;
+
+    fn write(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        data: [u8; 2],
+        password: Option<Password>,
+    ) -> Gen2Result<()>;
+
+    /// Gen2 Lock with the 20-bit mask and action payload, right-aligned in
+    /// three bytes (see `lock_payload`).
+    fn lock(&mut self, payload: [u8; 3], password: Option<Password>) -> Gen2Result<()>;
+
+    fn tune(&mut self, algorithm: TuningAlgorithm) -> Gen2Result<()>;
+
+    /// Reflected power (I, Q) at `frequency` in kHz, with the tuner settings applied.
+    fn reflected_power(&mut self, frequency: u32) -> Gen2Result<(i8, i8)>;
+
+    /// Whether accesses that bring no password go out with one anyway.
+    fn adds_password(&self) -> bool {
+        false
+    }
+}
+
+impl Gen2Transport for Gen2Reader {
+    fn inventory_once(&mut self) -> Gen2Result<(InventoryStatistics, Vec<InventoryTag>)> {
+        Gen2Reader::inventory_once(self)
+    }
+
+    fn inventory(
+        &mut self,
+        rounds: u32,
+        callback: Box<dyn Fn(InventoryTag)>,
+    ) -> Gen2Result<InventoryStatistics> {
+        Gen2Reader::inventory(self, rounds, callback)
+    }
+
+    fn select(&mut self, epc: &HexID) -> Gen2Result<()> {
+        Gen2Reader::select(self, epc)
+    }
+
+    fn read_alt(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        num_words: u8,
+        password: Option<Password>,
+    ) -> Gen2Result<Vec<u8>> {
+        Gen2Reader::read_alt(self, bank, address, num_words, password)
+    }
+
+    fn write(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        data: [u8; 2],
+        password: Option<Password>,
+    ) -> Gen2Result<()> {
+        Gen2Reader::write(self, bank, address, data, password)
+    }
+
+    fn lock(&mut self, payload: [u8; 3], password: Option<Password>) -> Gen2Result<()> {
+        // Lock not implemented in the wrapper
+        unsafe {
+            use libstuhfl_sys as ffi;
+
+            let mut param = ffi::STUHFL_T_Gen2_Lock {
+                mask: payload,
+                pwd: password.map_or([0; 4], Into::into),
+                tagReply: 0,
+            };
+
+            // error handling is normally contained in the wrapper
+            match ffi::Gen2_Lock(&mut param) {
+                0 => Ok(()),
+                status => Err(stuhfl_error(status)),
+            }
+        }
+    }
+
+    fn tune(&mut self, algorithm: TuningAlgorithm) -> Gen2Result<()> {
+        Gen2Reader::tune(self, algorithm)
+    }
+
+    fn reflected_power(&mut self, frequency: u32) -> Gen2Result<(i8, i8)> {
+        // Reflected power not implemented in the wrapper
+        unsafe {
+            use libstuhfl_sys as ffi;
+
+            let mut param = ffi::STUHFL_T_ST25RU3993_FreqReflectedPowerInfo {
+                frequency,
+                applyTunerSetting: true,
+                reflectedI: 0,
+                reflectedQ: 0,
+            };
+
+            // error handling is normally contained in the wrapper
+            match ffi::Get_FreqReflectedPower(&mut param) {
+                0 => Ok((param.reflectedI, param.reflectedQ)),
+                status => Err(stuhfl_error(status)),
+            }
+        }
+    }
+}
+
+/// The error for a nonzero STUHFL status code, as the libstuhfl wrapper
+/// reports it for the calls it covers.
+fn stuhfl_error(status: libstuhfl_sys::STUHFL_T_RET_CODE) -> StuhflError {
+    use libstuhfl_sys as ffi;
+
+    match status {
+        ffi::STUHFL_ERR_CHIP_NORESP => StuhflError::ChipNoResp,
+        ffi::STUHFL_ERR_CHIP_CRCERROR => StuhflError::ChipCrcError,
+        ffi::STUHFL_ERR_TIMEOUT => StuhflError::Timeout,
+        ffi::STUHFL_ERR_GEN2_ERRORCODE_MEMOVER => StuhflError::Gen2MemOverrun,
+        ffi::STUHFL_ERR_GEN2_ERRORCODE_MEMLOCKED => StuhflError::Gen2MemLocked,
+        ffi::STUHFL_ERR_GEN2_ERRORCODE_INSUFFICIENTPOWER => StuhflError::Gen2InsufficientPower,
+        ffi::STUHFL_ERR_GEN2_ERRORCODE_OTHER | ffi::STUHFL_ERR_GEN2_ERRORCODE_NONSPECIFIC => {
+            StuhflError::Gen2Other
+        }
+        _ => StuhflError::Generic,
+    }
+}
+
+/// Upper-case hex string with no separators.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+/// Parses hex digits into bytes, skipping any separators.
+fn parse_hex(hex: &str) -> Vec<u8> {
+    let digits: Vec<u8> = hex
+        .chars()
+        .filter_map(|c| c.to_digit(16))
+        .map(|d| d as u8)
+        .collect();
+    digits.chunks(2).map(|pair| pair.iter().fold(0, |acc, d| acc << 4 | d)).collect()
+}
+
+/// Parses the hex string an ID displays as back into bytes.
+fn hex_bytes(id: &HexID) -> Vec<u8> {
+    parse_hex(&format!("{id}"))
+}
+
+/// In-process model of a single EM4325 tag. Covers the user memory words the
+/// configuration and sensor procedures touch, the TID bank (including the
+/// factory calibration word at 0x0D), the EPC and password banks with Gen2
+/// locking, and an on-chip temperature measurement.
+struct SimEm4325 {
+    epc: Vec<u8>,
+    /// Protocol control word (EPC bank word 1).
+    pc: u16,
+    tid: Vec<u8>,
+    user: Vec<u16>,
+    /// Kill password (words 0-1) and access password (words 2-3).
+    reserved: [u16; 4],
+    /// Lock bits per `LockTarget`: bit 1 password-locked, bit 0 permalocked.
+    locks: [u8; 5],
+    /// Temperature seen by the on-chip sensor, in °C.
     temperature: f32,
+    /// Error of the untrimmed sensor, in °C.
+    sensor_error: f32,
+    /// Consecutive out-of-limit measurements, for the alarm delay.
+    out_of_limit_run: u8,
+    selected: bool,
+}
+
+impl SimEm4325 {
+    const USER_WORDS: usize = 0x110;
+    const TID_CALIBRATION_WORD: usize = 0x0D;
+
+    fn new() -> Self {
+        Self::with_ids(
+            &[0xE2, 0x00, 0x42, 0x53, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01],
+            0x5A20,
+        )
+    }
+
+    /// Creates a tag with the given EPC and factory calibration word. The
+    /// calibration word is copied into user word 0xEF, as it is on a fresh tag.
+    fn with_ids(epc: &[u8], calibration: u16) -> Self {
+        let mut tid = vec![0u8; 0x0E * 2];
+        tid[..4].copy_from_slice(&[0xE2, 0x00, 0xB0, 0x04]);
+        tid[4..8].copy_from_slice(&epc[epc.len() - 4..]);
+        tid[Self::TID_CALIBRATION_WORD * 2..][..2].copy_from_slice(&calibration.to_be_bytes());
+
+        let mut user = vec![0u16; Self::USER_WORDS];
+        user[CALIBRATION_WORD as usize] = calibration;
+
+        Self {
+            epc: epc.to_vec(),
+            pc: (epc.len() as u16 / 2) << 11,
+            tid,
+            user,
+            reserved: [0; 4],
+            locks: [0; 5],
+            temperature: 22.0,
+            sensor_error: 0.0,
+            out_of_limit_run: 0,
+            selected: false,
+        }
+    }
+
+    fn epc(&self) -> HexID {
+        HexID::from(self.epc.clone())
+    }
+
+    fn tid(&self) -> HexID {
+        HexID::from(self.tid[..12].to_vec())
+    }
+
+    fn user_word(&self, address: u32) -> u16 {
+        self.user[address as usize]
+    }
+
+    fn set_user_word(&mut self, address: u32, value: u16) {
+        self.user[address as usize] = value;
+    }
+
+    /// The tag's inventory reply. In simple-sensor mode the tag measures as it
+    /// answers and the sensor data words follow the EPC.
+    fn inventory_tag(&mut self) -> InventoryTag {
+        let mut epc = self.epc.clone();
+        if bit(self.pc, PC_XI_BIT) {
+            self.measure();
+            for address in [SENSOR_DATA_MSW, SENSOR_DATA_LSW] {
+                epc.extend(self.user_word(address).to_be_bytes());
+            }
+        }
+        InventoryTag {
+            epc: HexID::from(epc),
+            tid: self.tid(),
+            ..Default::default()
+        }
+    }
+
+    /// Fine trim from the low 5 bits of user word 0xEF, in °C.
+    fn fine_trim(&self) -> f32 {
+        let trim = (self.user_word(CALIBRATION_WORD) & 0x1F) as i8;
+        let trim = if trim & 0x10 != 0 { trim - 0x20 } else { trim };
+        trim as f32 * 0.25
+    }
+
+    /// Takes a measurement the way the chip does when the sensor data words are
+    /// read: the 9-bit two's complement temperature lands in the low bits of
+    /// 0x100 along with the valid, monitoring and battery-low status, and the
+    /// measurement counter in 0x101 is bumped.
+    fn measure(&mut self) {
+        let code = ((self.temperature + self.sensor_error + self.fine_trim()) * 4.0).round() as i16;
+        let code = (code.clamp(-255, 255) as u16) & 0x1FF;
+        let status = set_bit(true, 9)
+            | set_bit(bit(self.user_word(TEMP_SENSOR_CONTROL_WORD_3), 15), 10)
+            | set_bit(bit(self.user_word(BATTERY_MANAGEMENT_WORD_2), 14), 12);
+
+        let msw = self.user_word(SENSOR_DATA_MSW);
+        self.set_user_word(SENSOR_DATA_MSW, msw & 0xE800 | status | code);
+        let lsw = self.user_word(SENSOR_DATA_LSW);
+        self.set_user_word(SENSOR_DATA_LSW, lsw.wrapping_add(1));
+        self.check_limits(code);
+    }
+
+    /// Compares a measurement against the limits in 0xEC/0xED. Out-of-limit
+    /// samples are counted in TOTAL, and once more than `alarm_delay` of them
+    /// come in a row the matching alarm bit in 0x100 latches.
+    fn check_limits(&mut self, code: u16) {
+        let control = TempSensorControl::from_words([
+            self.user_word(TEMP_SENSOR_CONTROL_WORD_1),
+            self.user_word(TEMP_SENSOR_CONTROL_WORD_2),
+            self.user_word(TEMP_SENSOR_CONTROL_WORD_3),
+        ]);
+        let temperature = process_temp(code);
+        let under = control.under_alarm_enable && temperature < process_temp(control.under_limit);
+        let over = control.over_alarm_enable && temperature > process_temp(control.over_limit);
+        if !under && !over {
+            self.out_of_limit_run = 0;
+            return;
+        }
+
+        self.out_of_limit_run = self.out_of_limit_run.saturating_add(1);
+        let total = self.user_word(TOTAL_WORD);
+        self.set_user_word(TOTAL_WORD, total.saturating_add(1));
+        if self.out_of_limit_run > control.alarm_delay {
+            let msw = self.user_word(SENSOR_DATA_MSW);
+            self.set_user_word(SENSOR_DATA_MSW, msw | set_bit(over, 14) | set_bit(under, 13));
+        }
+    }
+
+    /// Sets the level seen on pin `pin`. A rising edge on an aux pin latches an
+    /// event in the I/O state word and the aux event flag in 0x100.
+    fn set_pin_input(&mut self, pin: u8, level: bool) {
+        let state = self.user_word(IO_STATE_WORD);
+        let was = bit(state, 4 + pin as u16);
+        let mut state = state & !(1 << (4 + pin)) | set_bit(level, 4 + pin as u16);
+
+        let io = IoControl::from_word(self.user_word(IO_CONTROL_WORD));
+        if level && !was && io.pin_mode(pin) == PinMode::Aux {
+            state |= 1 << pin;
+            let msw = self.user_word(SENSOR_DATA_MSW);
+            self.set_user_word(SENSOR_DATA_MSW, msw | 0x8000);
+        }
+        self.set_user_word(IO_STATE_WORD, state);
+    }
+
+    /// Lets `minutes` pass with no reader in range. With monitoring enabled the
+    /// tag samples on its own every `sample_interval` minutes.
+    fn monitor(&mut self, minutes: u32) {
+        let control = TempSensorControl::from_words([
+            self.user_word(TEMP_SENSOR_CONTROL_WORD_1),
+            self.user_word(TEMP_SENSOR_CONTROL_WORD_2),
+            self.user_word(TEMP_SENSOR_CONTROL_WORD_3),
+        ]);
+        if !control.monitor_enable || control.sample_interval == 0 {
+            return;
+        }
+        for _ in 0..minutes / control.sample_interval as u32 {
+            self.measure();
+        }
+    }
+
+    fn access_password(&self) -> [u8; 4] {
+        let [hi, lo] = [self.reserved[2], self.reserved[3]].map(u16::to_be_bytes);
+        [hi[0], hi[1], lo[0], lo[1]]
+    }
+
+    /// A tag with a zero access password goes straight to the secured state;
+    /// otherwise the right password has to come with the command.
+    fn secured(&self, password: Option<Password>) -> bool {
+        self.access_password() == [0; 4] || password == Some(Password::from(self.access_password()))
+    }
+
+    /// A wrong password fails the Access command, after which the tag doesn't
+    /// answer. With a zero access password any password is let through.
+    fn check_password(&self, password: Option<Password>) -> Gen2Result<()> {
+        match password {
+            Some(password) if !self.secured(Some(password)) => Err(StuhflError::ChipNoResp),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the lock bits for `target` before a write, or before a read of
+    /// the password bank.
+    fn check_lock(&self, target: LockTarget, password: Option<Password>) -> Gen2Result<()> {
+        match self.locks[target as usize] {
+            0b11 => Err(StuhflError::Gen2MemLocked),
+            0b10 if !self.secured(password) => Err(StuhflError::Gen2MemLocked),
+            _ => Ok(()),
+        }
+    }
+
+    fn password_target(word: usize) -> LockTarget {
+        if word < 2 {
+            LockTarget::KillPassword
+        } else {
+            LockTarget::AccessPassword
+        }
+    }
+
+    /// EPC bank contents: StoredCRC (not modelled, always 0), PC, then the EPC.
+    fn epc_bank(&self) -> Vec<u16> {
+        let mut words = vec![0, self.pc];
+        words.extend(self.epc.chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])));
+        words
+    }
+
+    fn check_access(&self, words: std::ops::Range<usize>, len: usize) -> Gen2Result<()> {
+        if !self.selected {
+            return Err(StuhflError::ChipNoResp);
+        }
+        if words.end > len {
+            return Err(StuhflError::Gen2MemOverrun);
+        }
+        Ok(())
+    }
+}
+
+impl Gen2Transport for SimEm4325 {
+    fn inventory_once(&mut self) -> Gen2Result<(InventoryStatistics, Vec<InventoryTag>)> {
+        Ok((InventoryStatistics::default(), vec![self.inventory_tag()]))
+    }
+
+    fn inventory(
+        &mut self,
+        rounds: u32,
+        callback: Box<dyn Fn(InventoryTag)>,
+    ) -> Gen2Result<InventoryStatistics> {
+        for _ in 0..rounds {
+            callback(self.inventory_tag());
+        }
+        Ok(InventoryStatistics::default())
+    }
+
+    fn select(&mut self, epc: &HexID) -> Gen2Result<()> {
+        self.selected = *epc == self.epc();
+        Ok(())
+    }
+
+    fn read_alt(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        num_words: u8,
+        password: Option<Password>,
+    ) -> Gen2Result<Vec<u8>> {
+        self.check_password(password)?;
+        let words = address as usize..address as usize + num_words as usize;
+        match bank {
+            MemoryBank::Reserved => {
+                self.check_access(words.clone(), self.reserved.len())?;
+                for word in words.clone() {
+                    self.check_lock(Self::password_target(word), password)?;
+                }
+                Ok(self.reserved[words].iter().flat_map(|w| w.to_be_bytes()).collect())
+            }
+            MemoryBank::Epc => {
+                let bank = self.epc_bank();
+                self.check_access(words.clone(), bank.len())?;
+                Ok(bank[words].iter().flat_map(|w| w.to_be_bytes()).collect())
+            }
+            MemoryBank::User => {
+                self.check_access(words.clone(), Self::USER_WORDS)?;
+                if words.contains(&(SENSOR_DATA_MSW as usize)) {
+                    self.measure();
+                }
+                Ok(self.user[words].iter().flat_map(|w| w.to_be_bytes()).collect())
+            }
+            MemoryBank::Tid => {
+                self.check_access(words.clone(), self.tid.len() / 2)?;
+                Ok(self.tid[words.start * 2..words.end * 2].to_vec())
+            }
+            _ => Err(StuhflError::Gen2Other),
+        }
+    }
+
+    fn write(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        data: [u8; 2],
+        password: Option<Password>,
+    ) -> Gen2Result<()> {
+        self.check_password(password)?;
+        let word = address as usize;
+        match bank {
+            MemoryBank::Reserved => {
+                self.check_access(word..word + 1, self.reserved.len())?;
+                self.check_lock(Self::password_target(word), password)?;
+                self.reserved[word] = u16::from_be_bytes(data);
+                Ok(())
+            }
+            MemoryBank::Epc => {
+                self.check_access(word..word + 1, self.epc_bank().len())?;
+                self.check_lock(LockTarget::Epc, password)?;
+                match word {
+                    0 => {}
+                    1 => self.pc = u16::from_be_bytes(data),
+                    _ => self.epc[(word - 2) * 2..][..2].copy_from_slice(&data),
+                }
+                Ok(())
+            }
+            MemoryBank::User => {
+                self.check_access(word..word + 1, Self::USER_WORDS)?;
+                self.check_lock(LockTarget::User, password)?;
+                let mut value = u16::from_be_bytes(data);
+                match address {
+                    // battery status bits are set by the chip
+                    BATTERY_MANAGEMENT_WORD_2 => {
+                        value = value & 0x3FFF | self.user_word(address) & 0xC000;
+                    }
+                    _ => {}
+                }
+                self.set_user_word(address, value);
+                Ok(())
+            }
+            MemoryBank::Tid => {
+                self.check_access(word..word + 1, self.tid.len() / 2)?;
+                self.check_lock(LockTarget::Tid, password)?;
+                self.tid[word * 2..][..2].copy_from_slice(&data);
+                Ok(())
+            }
+            _ => Err(StuhflError::Gen2Other),
+        }
+    }
+
+    fn lock(&mut self, payload: [u8; 3], password: Option<Password>) -> Gen2Result<()> {
+        if !self.selected {
+            return Err(StuhflError::ChipNoResp);
+        }
+        self.check_password(password)?;
+        if !self.secured(password) {
+            return Err(StuhflError::Gen2MemLocked);
+        }
+        let payload = u32::from_be_bytes([0, payload[0], payload[1], payload[2]]);
+        let mut locks = self.locks;
+        for (i, lock) in locks.iter_mut().enumerate() {
+            let mask = (payload >> (18 - 2 * i) & 0b11) as u8;
+            let action = (payload >> (8 - 2 * i) & 0b11) as u8;
+            let new = *lock & !mask | action & mask;
+            // a permalocked area can't change any more
+            if *lock & 0b01 != 0 && new != *lock {
+                return Err(StuhflError::Gen2MemLocked);
+            }
+            *lock = new;
+        }
+        self.locks = locks;
+        Ok(())
+    }
+
+    fn tune(&mut self, _algorithm: TuningAlgorithm) -> Gen2Result<()> {
+        Ok(())
+    }
+
+    fn reflected_power(&mut self, _frequency: u32) -> Gen2Result<(i8, i8)> {
+        Ok((3, -2))
+    }
+}
+
+/// Register-level model of the ADXL363 on the purple tags: ID registers,
+/// measurement on/off, the FIFO filling at the configured output data rate,
+/// and self-test deflection. Time only passes when `advance` is called.
+struct SimAdxl363 {
+    registers: [u8; 0x40],
+    fifo: std::collections::VecDeque<u16>,
+    /// Static acceleration applied to the part, in mg per axis.
+    acceleration: [i16; 3],
+    /// Output change while the ST bit is set, in mg per axis.
+    self_test_deflection: [i16; 3],
+    /// Time accumulated towards the next sample.
+    pending: std::time::Duration,
+}
+
+impl SimAdxl363 {
+    const DEVID_AD: usize = 0x00;
+    const FIFO_ENTRIES_L: usize = 0x0C;
+    const XDATA_L: usize = 0x0E;
+    const SOFT_RESET: usize = 0x1F;
+    const FIFO_CONTROL: usize = 0x28;
+    const FILTER_CTL: usize = 0x2C;
+    const POWER_CTL: usize = 0x2D;
+    const SELF_TEST: usize = 0x2E;
+    const FIFO_WORDS: usize = 512;
+    /// Output change while the ST bit is set, in mg (middle of the datasheet limits).
+    const SELF_TEST_DEFLECTION: [i16; 3] = [580, -580, 470];
+    /// Fastest SPI clock the part takes.
+    const MAX_SCLK_KHZ: u16 = 8000;
+
+    fn new() -> Self {
+        let mut registers = [0u8; 0x40];
+        registers[Self::DEVID_AD..Self::DEVID_AD + 4].copy_from_slice(&[0xAD, 0x1D, 0xF3, 0x02]);
+        registers[Self::FILTER_CTL] = 0x13;
+
+        Self {
+            registers,
+            fifo: std::collections::VecDeque::new(),
+            acceleration: [0, 0, 1000],
+            self_test_deflection: Self::SELF_TEST_DEFLECTION,
+            pending: std::time::Duration::ZERO,
+        }
+    }
+
+    fn measuring(&self) -> bool {
+        self.registers[Self::POWER_CTL] & 0b11 == 0b10
+    }
+
+    fn odr_hz(&self) -> f32 {
+        12.5 * (1 << (self.registers[Self::FILTER_CTL] & 0b111).min(5)) as f32
+    }
+
+    /// mg per LSB for the range selected in FILTER_CTL.
+    fn scale_mg(&self) -> i16 {
+        match self.registers[Self::FILTER_CTL] >> 6 {
+            0 => 1,
+            1 => 2,
+            _ => 4,
+        }
+    }
+
+    fn output(&self) -> [i16; 3] {
+        let mut output = self.acceleration;
+        if self.registers[Self::SELF_TEST] & 0x01 != 0 {
+            for (axis, deflection) in output.iter_mut().zip(self.self_test_deflection) {
+                *axis += deflection;
+            }
+        }
+        output.map(|mg| mg / self.scale_mg())
+    }
+
+    fn push_fifo(&mut self, word: u16) {
+        if self.fifo.len() == Self::FIFO_WORDS {
+            // oldest-saved mode stops filling, stream and triggered drop the oldest
+            if self.registers[Self::FIFO_CONTROL] & 0b11 == 0b01 {
+                return;
+            }
+            self.fifo.pop_front();
+        }
+        self.fifo.push_back(word);
+    }
+
+    fn take_sample(&mut self) {
+        let output = self.output();
+        for (i, value) in output.iter().enumerate() {
+            self.registers[Self::XDATA_L + i * 2..][..2].copy_from_slice(&value.to_le_bytes());
+        }
+        if self.registers[Self::FIFO_CONTROL] & 0b11 == 0 {
+            return;
+        }
+        for (axis, value) in output.into_iter().enumerate() {
+            self.push_fifo((axis as u16) << 14 | (value as u16 & 0x3FFF));
+        }
+        if self.registers[Self::FIFO_CONTROL] & 0b100 != 0 {
+            self.push_fifo(0b11 << 14 | 350);
+        }
+        self.update_fifo_entries();
+    }
+
+    fn update_fifo_entries(&mut self) {
+        let entries = (self.fifo.len() as u16).to_le_bytes();
+        self.registers[Self::FIFO_ENTRIES_L..][..2].copy_from_slice(&entries);
+    }
+
+    /// Lets `elapsed` pass, taking samples at the output data rate while
+    /// measurement is on.
+    fn advance(&mut self, elapsed: std::time::Duration) {
+        if !self.measuring() {
+            return;
+        }
+        let period = std::time::Duration::from_secs_f32(1.0 / self.odr_hz());
+        self.pending += elapsed;
+        while self.pending >= period {
+            self.pending -= period;
+            self.take_sample();
+        }
+    }
+
+    /// Reads `len` registers from `address` on; past the last one reads zero.
+    fn register_bytes(&self, address: u8, len: u16) -> Vec<u8> {
+        (0..len as usize)
+            .map(|i| self.registers.get(address as usize + i).copied().unwrap_or(0))
+            .collect()
+    }
+
+    fn set_registers(&mut self, address: u8, data: &[u8]) {
+        if address as usize == Self::SOFT_RESET && data.first() == Some(&ADXL_SOFT_RESET_CODE) {
+            // back to the power-on registers with an empty FIFO; what the
+            // part is sensing doesn't change
+            *self = Self {
+                acceleration: self.acceleration,
+                self_test_deflection: self.self_test_deflection,
+                ..Self::new()
+            };
+            return;
+        }
+        let was_measuring = self.measuring();
+        for (i, byte) in data.iter().enumerate() {
+            let register = address as usize + i;
+            // ID, status and data registers are read-only
+            if let Some(value) = self.registers.get_mut(register).filter(|_| register >= 0x20) {
+                *value = *byte;
+            }
+        }
+        if !was_measuring && self.measuring() {
+            // switching to measurement mode leaves one settling sample in the
+            // FIFO before the first real one
+            self.pending = std::time::Duration::ZERO;
+            for axis in 0..3u16 {
+                self.push_fifo(axis << 14);
+            }
+            self.update_fifo_entries();
+        }
+    }
+
+    /// Pops `num_bytes` of FIFO data, little-endian, as the FIFO read command does.
+    fn pop_fifo(&mut self, num_bytes: u16) -> Vec<u8> {
+        let bytes = (0..num_bytes / 2)
+            .flat_map(|_| self.fifo.pop_front().unwrap_or(0).to_le_bytes())
+            .collect();
+        self.update_fifo_entries();
+        bytes
+    }
+
+    /// Answers one SPI transfer as the part's slave interface does: a command
+    /// byte, the register address for register commands, then data. Only
+    /// mode 0 up to `MAX_SCLK_KHZ` is decoded; otherwise MISO reads back high.
+    fn spi(&mut self, config: SpiConfig, tx: &[u8], rx_len: usize) -> Vec<u8> {
+        if config.mode != SpiMode::Mode0 || config.clock_khz > Self::MAX_SCLK_KHZ {
+            return vec![0xFF; rx_len];
+        }
+        match *tx {
+            [ADXL_WRITE_REGISTER, address, ref data @ ..] => {
+                self.set_registers(address, data);
+                vec![0; rx_len]
+            }
+            [ADXL_READ_REGISTER, address] => self.register_bytes(address, rx_len as u16),
+            [ADXL_READ_FIFO] => self.pop_fifo(rx_len as u16),
+            _ => vec![0xFF; rx_len],
+        }
+    }
+}
+
+/// The part alone on the bus, as if wired straight to the master.
+impl SpiMaster for SimAdxl363 {
+    fn spi_transfer(&mut self, config: SpiConfig, tx: &[u8], rx_len: usize) -> Gen2Result<Vec<u8>> {
+        Ok(self.spi(config, tx, rx_len))
+    }
+}
+
+/// A purple tag: an EM4325 with the ADXL363 on its SPI pins, on a virtual
+/// clock. The sensor catches up with the clock before every command, so its
+/// FIFO fills while a procedure sleeps or polls the field.
+struct SimPurpleTag<'a> {
+    tag: SimEm4325,
+    adxl: SimAdxl363,
+    clock: &'a VirtualClock,
+    /// Clock time the sensor has been advanced to.
+    synced: std::time::Duration,
+}
+
+impl<'a> SimPurpleTag<'a> {
+    fn new(tag: SimEm4325, clock: &'a VirtualClock) -> Self {
+        Self {
+            tag,
+            adxl: SimAdxl363::new(),
+            clock,
+            synced: clock.elapsed(),
+        }
+    }
+
+    fn sync(&mut self) {
+        let now = self.clock.elapsed();
+        self.adxl.advance(now - self.synced);
+        self.synced = now;
+    }
+}
+
+impl Gen2Transport for SimPurpleTag<'_> {
+    fn inventory_once(&mut self) -> Gen2Result<(InventoryStatistics, Vec<InventoryTag>)> {
+        self.sync();
+        self.tag.inventory_once()
+    }
+
+    fn inventory(
+        &mut self,
+        rounds: u32,
+        callback: Box<dyn Fn(InventoryTag)>,
+    ) -> Gen2Result<InventoryStatistics> {
+        self.sync();
+        self.tag.inventory(rounds, callback)
+    }
+
+    fn select(&mut self, epc: &HexID) -> Gen2Result<()> {
+        self.sync();
+        self.tag.select(epc)
+    }
+
+    fn read_alt(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        num_words: u8,
+        password: Option<Password>,
+    ) -> Gen2Result<Vec<u8>> {
+        self.sync();
+        self.tag.read_alt(bank, address, num_words, password)
+    }
+
+    fn write(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        data: [u8; 2],
+        password: Option<Password>,
+    ) -> Gen2Result<()> {
+        self.sync();
+        self.tag.write(bank, address, data, password)
+    }
+
+    fn lock(&mut self, payload: [u8; 3], password: Option<Password>) -> Gen2Result<()> {
+        self.sync();
+        self.tag.lock(payload, password)
+    }
+
+    fn tune(&mut self, algorithm: TuningAlgorithm) -> Gen2Result<()> {
+        self.tag.tune(algorithm)
+    }
+
+    fn reflected_power(&mut self, frequency: u32) -> Gen2Result<(i8, i8)> {
+        self.tag.reflected_power(frequency)
+    }
+
+    fn adds_password(&self) -> bool {
+        self.tag.adds_password()
+    }
+}
+
+/// The EM4325 only drives its SPI pins while it is the selected tag.
+impl SpiMaster for SimPurpleTag<'_> {
+    fn spi_transfer(&mut self, config: SpiConfig, tx: &[u8], rx_len: usize) -> Gen2Result<Vec<u8>> {
+        if !self.tag.selected {
+            return Err(StuhflError::ChipNoResp);
+        }
+        self.sync();
+        Ok(self.adxl.spi(config, tx, rx_len))
+    }
+}
+
+/// Small xorshift generator so simulated fields are reproducible from a seed.
+struct SimRng(u64);
+
+impl SimRng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// A tag placed in a `SimField`.
+struct SimFieldTag {
+    tag: SimEm4325,
+    /// Log RSSI the reader reports for this tag.
+    rssi: u8,
+    /// Probability that the tag misses any single inventory round.
+    dropout: f32,
+    /// Whether the tag is in the field at all.
+    present: bool,
+}
+
+impl SimFieldTag {
+    fn new(tag: SimEm4325, rssi: u8, dropout: f32) -> Self {
+        Self {
+            tag,
+            rssi,
+            dropout,
+            present: true,
+        }
+    }
+
+    /// Places a tag `metres` from the antenna, with RSSI falling off 20 dB per
+    /// decade of distance from 0.1 m.
+    fn at_distance(tag: SimEm4325, metres: f32, dropout: f32) -> Self {
+        let rssi = 80.0 - 20.0 * (metres / 0.1).max(1.0).log10();
+        Self::new(tag, rssi.clamp(0.0, 255.0) as u8, dropout)
+    }
+}
+
+/// RF field holding several simulated tags. Every inventory round each present
+/// tag answers unless it drops out, so rounds see different subsets of tags.
+/// Select and memory access go to whichever tag matched the last select.
+struct SimField {
+    tags: Vec<SimFieldTag>,
+    rng: SimRng,
+}
+
+impl SimField {
+    fn new(seed: u64) -> Self {
+        Self {
+            tags: Vec::new(),
+            rng: SimRng::new(seed),
+        }
+    }
+
+    fn add(&mut self, tag: SimFieldTag) -> HexID {
+        let epc = tag.tag.epc();
+        self.tags.push(tag);
+        epc
+    }
+
+    fn tag_mut(&mut self, epc: &HexID) -> Option<&mut SimFieldTag> {
+        self.tags.iter_mut().find(|t| t.tag.epc() == *epc)
+    }
+
+    /// Runs one round, returning the indices of the tags that answered.
+    fn round(&mut self) -> Vec<usize> {
+        let mut answered = Vec::new();
+        for i in 0..self.tags.len() {
+            let roll = self.rng.next_f32();
+            if self.tags[i].present && roll >= self.tags[i].dropout {
+                answered.push(i);
+            }
+        }
+        answered
+    }
+
+    fn stats(&self, rssi: &[u8]) -> InventoryStatistics {
+        let rssi_log_mean = if rssi.is_empty() {
+            0
+        } else {
+            (rssi.iter().map(|&r| r as u32).sum::<u32>() / rssi.len() as u32) as u8
+        };
+        InventoryStatistics {
+            rssi_log_mean,
+            ..Default::default()
+        }
+    }
+
+    fn selected(&mut self) -> Gen2Result<&mut SimEm4325> {
+        match self.tags.iter_mut().find(|t| t.tag.selected) {
+            Some(t) if t.present => Ok(&mut t.tag),
+            _ => Err(StuhflError::ChipNoResp),
+        }
+    }
+}
+
+impl Gen2Transport for SimField {
+    fn inventory_once(&mut self) -> Gen2Result<(InventoryStatistics, Vec<InventoryTag>)> {
+        let answered = self.round();
+        let rssi: Vec<u8> = answered.iter().map(|&i| self.tags[i].rssi).collect();
+        let tags = answered.iter().map(|&i| self.tags[i].tag.inventory_tag()).collect();
+        Ok((self.stats(&rssi), tags))
+    }
+
+    fn inventory(
+        &mut self,
+        rounds: u32,
+        callback: Box<dyn Fn(InventoryTag)>,
+    ) -> Gen2Result<InventoryStatistics> {
+        let mut rssi = Vec::new();
+        for _ in 0..rounds {
+            for i in self.round() {
+                rssi.push(self.tags[i].rssi);
+                callback(self.tags[i].tag.inventory_tag());
+            }
+        }
+        Ok(self.stats(&rssi))
+    }
+
+    fn select(&mut self, epc: &HexID) -> Gen2Result<()> {
+        for t in &mut self.tags {
+            t.tag.select(epc)?;
+        }
+        Ok(())
+    }
+
+    fn read_alt(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        num_words: u8,
+        password: Option<Password>,
+    ) -> Gen2Result<Vec<u8>> {
+        self.selected()?.read_alt(bank, address, num_words, password)
+    }
+
+    fn write(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        data: [u8; 2],
+        password: Option<Password>,
+    ) -> Gen2Result<()> {
+        self.selected()?.write(bank, address, data, password)
+    }
+
+    fn lock(&mut self, payload: [u8; 3], password: Option<Password>) -> Gen2Result<()> {
+        self.selected()?.lock(payload, password)
+    }
+
+    fn tune(&mut self, _algorithm: TuningAlgorithm) -> Gen2Result<()> {
+        Ok(())
+    }
+
+    fn reflected_power(&mut self, _frequency: u32) -> Gen2Result<(i8, i8)> {
+        Ok((3, -2))
+    }
+}
+
+/// Maps a recorded error name back to the libstuhfl error it came from.
+fn error_from_name(name: &str) -> StuhflError {
+    match name {
+        "ChipNoResp" => StuhflError::ChipNoResp,
+        "ChipCrcError" => StuhflError::ChipCrcError,
+        "Timeout" => StuhflError::Timeout,
+        "Gen2MemOverrun" => StuhflError::Gen2MemOverrun,
+        "Gen2MemLocked" => StuhflError::Gen2MemLocked,
+        "Gen2InsufficientPower" => StuhflError::Gen2InsufficientPower,
+        "Gen2Other" => StuhflError::Gen2Other,
+        _ => StuhflError::Generic,
+    }
+}
+
+/// `rssi;epc:tid,epc:tid` as stored for inventory results.
+fn format_inventory(stats: &InventoryStatistics, tags: &[InventoryTag]) -> String {
+    let tags: Vec<String> = tags.iter().map(|t| format!("{}:{}", t.epc, t.tid)).collect();
+    format!("{};{}", stats.rssi_log_mean, tags.join(","))
+}
+
+fn parse_inventory(payload: &str) -> (InventoryStatistics, Vec<InventoryTag>) {
+    let (rssi, tags) = payload.split_once(';').unwrap_or((payload, ""));
+    let stats = InventoryStatistics {
+        rssi_log_mean: rssi.parse().unwrap_or(0),
+        ..Default::default()
+    };
+    let tags = tags
+        .split(',')
+        .filter_map(|t| t.split_once(':'))
+        .map(|(epc, tid)| InventoryTag {
+            epc: HexID::from(parse_hex(epc)),
+            tid: HexID::from(parse_hex(tid)),
+            ..Default::default()
+        })
+        .collect();
+    (stats, tags)
+}
+
+/// Wraps a reader and appends every call to a capture file, one line each:
+/// start time (ms), duration (µs), call, arguments, `ok`/`err` and the result.
+/// Lines are flushed as they are written so a crash still leaves a usable
+/// capture. Passwords are never written.
+struct RecordingReader<R: Gen2Transport> {
+    inner: R,
+    capture: std::fs::File,
+    start: std::time::Instant,
+}
+
+impl<R: Gen2Transport> RecordingReader<R> {
+    fn create(inner: R, path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        let mut capture = std::fs::File::create(path)?;
+        writeln!(capture, "# st25ru3993 capture v1")?;
+        Ok(Self {
+            inner,
+            capture,
+            start: std::time::Instant::now(),
+        })
+    }
+
+    fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn record<T>(
+        &mut self,
+        call: &str,
+        args: String,
+        f: impl FnOnce(&mut R) -> Gen2Result<T>,
+        payload: impl FnOnce(&T) -> String,
+    ) -> Gen2Result<T> {
+        let started = self.start.elapsed();
+        let result = f(&mut self.inner);
+        let elapsed = self.start.elapsed() - started;
+        let (status, payload) = match &result {
+            Ok(value) => ("ok", payload(value)),
+            Err(err) => ("err", format!("{err:?}")),
+        };
+        // a capture that can't be written shouldn't change what the caller sees
+        writeln!(
+            self.capture,
+            "{}\t{}\t{call}\t{args}\t{status}\t{payload}",
+            started.as_millis(),
+            elapsed.as_micros()
+        )
+        .and_then(|_| self.capture.flush())
+        .ok();
+        result
+    }
+}
+
+impl<R: Gen2Transport> Gen2Transport for RecordingReader<R> {
+    fn inventory_once(&mut self) -> Gen2Result<(InventoryStatistics, Vec<InventoryTag>)> {
+        self.record(
+            "inventory_once",
+            String::new(),
+            |r| r.inventory_once(),
+            |(stats, tags)| format_inventory(stats, tags),
+        )
+    }
+
+    fn inventory(
+        &mut self,
+        rounds: u32,
+        callback: Box<dyn Fn(InventoryTag)>,
+    ) -> Gen2Result<InventoryStatistics> {
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let tags = seen.clone();
+        let callback = Box::new(move |tag: InventoryTag| {
+            tags.borrow_mut().push(tag.clone());
+            callback(tag)
+        });
+        self.record(
+            "inventory",
+            rounds.to_string(),
+            |r| r.inventory(rounds, callback),
+            |stats| format_inventory(stats, &seen.borrow()),
+        )
+    }
+
+    fn select(&mut self, epc: &HexID) -> Gen2Result<()> {
+        self.record("select", epc.to_string(), |r| r.select(epc), |_| String::new())
+    }
+
+    fn read_alt(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        num_words: u8,
+        password: Option<Password>,
+    ) -> Gen2Result<Vec<u8>> {
+        self.record(
+            "read_alt",
+            format!("{bank:?} {address:X} {num_words}"),
+            |r| r.read_alt(bank, address, num_words, password),
+            |bytes| to_hex(bytes),
+        )
+    }
+
+    fn write(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        data: [u8; 2],
+        password: Option<Password>,
+    ) -> Gen2Result<()> {
+        self.record(
+            "write",
+            format!("{bank:?} {address:X} {}", to_hex(&data)),
+            |r| r.write(bank, address, data, password),
+            |_| String::new(),
+        )
+    }
+
+    fn lock(&mut self, payload: [u8; 3], password: Option<Password>) -> Gen2Result<()> {
+        self.record("lock", to_hex(&payload), |r| r.lock(payload, password), |_| String::new())
+    }
+
+    fn tune(&mut self, algorithm: TuningAlgorithm) -> Gen2Result<()> {
+        self.record("tune", format!("{algorithm:?}"), |r| r.tune(algorithm), |_| String::new())
+    }
+
+    fn reflected_power(&mut self, frequency: u32) -> Gen2Result<(i8, i8)> {
+        self.record(
+            "reflected_power",
+            frequency.to_string(),
+            |r| r.reflected_power(frequency),
+            |(i, q)| format!("{i} {q}"),
+        )
+    }
+
+    fn adds_password(&self) -> bool {
+        self.inner.adds_password()
+    }
+}
+
+/// One line of a capture file.
+struct RecordedCall {
+    call: String,
+    args: String,
+    result: Result<String, String>,
+}
+
+/// Serves the responses from a capture written by `RecordingReader`, in order.
+/// Panics if the code under test makes a different call than the one recorded,
+/// since the rest of the capture no longer applies.
+struct ReplayReader {
+    calls: std::collections::VecDeque<RecordedCall>,
+}
+
+impl ReplayReader {
+    fn open(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        let capture = std::fs::read_to_string(path)?;
+        let calls = capture
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.is_empty())
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.splitn(6, '\t').collect();
+                let [_, _, call, args, status, payload] = fields[..] else {
+                    return None;
+                };
+                Some(RecordedCall {
+                    call: call.to_string(),
+                    args: args.to_string(),
+                    result: match status {
+                        "ok" => Ok(payload.to_string()),
+                        _ => Err(payload.to_string()),
+                    },
+                })
+            })
+            .collect();
+        Ok(Self { calls })
+    }
+
+    fn next(&mut self, call: &str, args: String) -> Gen2Result<String> {
+        let recorded = self
+            .calls
+            .pop_front()
+            .unwrap_or_else(|| panic!("replay ran out of calls at {call}({args})"));
+        assert!(
+            recorded.call == call && recorded.args == args,
+            "replay diverged: recorded {}({}), got {call}({args})",
+            recorded.call,
+            recorded.args
+        );
+        recorded.result.map_err(|name| error_from_name(&name))
+    }
+}
+
+impl Gen2Transport for ReplayReader {
+    fn inventory_once(&mut self) -> Gen2Result<(InventoryStatistics, Vec<InventoryTag>)> {
+        Ok(parse_inventory(&self.next("inventory_once", String::new())?))
+    }
+
+    fn inventory(
+        &mut self,
+        rounds: u32,
+        callback: Box<dyn Fn(InventoryTag)>,
+    ) -> Gen2Result<InventoryStatistics> {
+        let (stats, tags) = parse_inventory(&self.next("inventory", rounds.to_string())?);
+        for tag in tags {
+            callback(tag);
+        }
+        Ok(stats)
+    }
+
+    fn select(&mut self, epc: &HexID) -> Gen2Result<()> {
+        self.next("select", epc.to_string()).map(|_| ())
+    }
+
+    fn read_alt(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        num_words: u8,
+        _password: Option<Password>,
+    ) -> Gen2Result<Vec<u8>> {
+        let args = format!("{bank:?} {address:X} {num_words}");
+        Ok(parse_hex(&self.next("read_alt", args)?))
+    }
+
+    fn write(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        data: [u8; 2],
+        _password: Option<Password>,
+    ) -> Gen2Result<()> {
+        let args = format!("{bank:?} {address:X} {}", to_hex(&data));
+        self.next("write", args).map(|_| ())
+    }
+
+    fn lock(&mut self, payload: [u8; 3], _password: Option<Password>) -> Gen2Result<()> {
+        self.next("lock", to_hex(&payload)).map(|_| ())
+    }
+
+    fn tune(&mut self, algorithm: TuningAlgorithm) -> Gen2Result<()> {
+        self.next("tune", format!("{algorithm:?}")).map(|_| ())
+    }
+
+    fn reflected_power(&mut self, frequency: u32) -> Gen2Result<(i8, i8)> {
+        let payload = self.next("reflected_power", frequency.to_string())?;
+        let (i, q) = payload.split_once(' ').unwrap_or(("0", "0"));
+        Ok((i.parse().unwrap_or(0), q.parse().unwrap_or(0)))
+    }
+}
+
+/// Failure a `FaultyReader` can inject.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fault {
+    TagNotResponding,
+    Crc,
+    Timeout,
+    /// The write reaches the tag but its reply is lost, so the word changes
+    /// and the caller still sees an error.
+    LostWriteReply,
+    /// The write is acknowledged but never reaches the tag, so the word keeps
+    /// its old value.
+    DroppedWrite,
+    /// Inventory succeeds but no tag answers. Other calls are unaffected.
+    EmptyInventory,
+}
+
+impl Fault {
+    fn error(self) -> StuhflError {
+        match self {
+            Fault::TagNotResponding | Fault::EmptyInventory => StuhflError::ChipNoResp,
+            Fault::Crc => StuhflError::ChipCrcError,
+            Fault::Timeout | Fault::LostWriteReply | Fault::DroppedWrite => StuhflError::Timeout,
+        }
+    }
+}
+
+/// When a fault rule fires. Call and write indices count from 0.
+#[derive(Clone, Copy, Debug)]
+enum FaultPoint {
+    /// The nth call of any kind.
+    Call(usize),
+    /// The nth write.
+    Write(usize),
+    /// Every write from the nth on, leaving a multi-word sequence partly written.
+    WritesFrom(usize),
+    /// Writes n up to (not including) m, e.g. a tag that stops answering for
+    /// a while.
+    Writes(usize, usize),
+    /// Any call, with the given probability.
+    Probability(f32),
+}
+
+/// Wraps a reader and injects Gen2 failures at chosen points or at random
+/// (seeded), so error paths can be exercised deterministically.
+struct FaultyReader<R: Gen2Transport> {
+    inner: R,
+    rules: Vec<(FaultPoint, Fault)>,
+    rng: SimRng,
+    calls: usize,
+    writes: usize,
+    /// Number of faults injected so far.
+    injected: usize,
+}
+
+impl<R: Gen2Transport> FaultyReader<R> {
+    fn new(inner: R, seed: u64) -> Self {
+        Self {
+            inner,
+            rules: Vec::new(),
+            rng: SimRng::new(seed),
+            calls: 0,
+            writes: 0,
+            injected: 0,
+        }
+    }
+
+    fn with(mut self, point: FaultPoint, fault: Fault) -> Self {
+        self.rules.push((point, fault));
+        self
+    }
+
+    fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Counts the call and returns the fault to inject, if any. Faults that
+    /// don't apply to this kind of call are skipped.
+    fn next_fault(&mut self, is_write: bool, is_inventory: bool) -> Option<Fault> {
+        let call = self.calls;
+        let write = self.writes;
+        self.calls += 1;
+        if is_write {
+            self.writes += 1;
+        }
+
+        for i in 0..self.rules.len() {
+            let (point, fault) = self.rules[i];
+            let applies = match fault {
+                Fault::LostWriteReply | Fault::DroppedWrite => is_write,
+                Fault::EmptyInventory => is_inventory,
+                _ => true,
+            };
+            let fires = match point {
+                FaultPoint::Call(n) => call == n,
+                FaultPoint::Write(n) => is_write && write == n,
+                FaultPoint::WritesFrom(n) => is_write && write >= n,
+                FaultPoint::Writes(n, m) => is_write && (n..m).contains(&write),
+                FaultPoint::Probability(p) => self.rng.next_f32() < p,
+            };
+            if applies && fires {
+                self.injected += 1;
+                return Some(fault);
+            }
+        }
+        None
+    }
+}
+
+impl<R: Gen2Transport> Gen2Transport for FaultyReader<R> {
+    fn inventory_once(&mut self) -> Gen2Result<(InventoryStatistics, Vec<InventoryTag>)> {
+        match self.next_fault(false, true) {
+            Some(Fault::EmptyInventory) => Ok((InventoryStatistics::default(), Vec::new())),
+            Some(fault) => Err(fault.error()),
+            None => self.inner.inventory_once(),
+        }
+    }
+
+    fn inventory(
+        &mut self,
+        rounds: u32,
+        callback: Box<dyn Fn(InventoryTag)>,
+    ) -> Gen2Result<InventoryStatistics> {
+        match self.next_fault(false, true) {
+            Some(Fault::EmptyInventory) => Ok(InventoryStatistics::default()),
+            Some(fault) => Err(fault.error()),
+            None => self.inner.inventory(rounds, callback),
+        }
+    }
+
+    fn select(&mut self, epc: &HexID) -> Gen2Result<()> {
+        match self.next_fault(false, false) {
+            Some(fault) => Err(fault.error()),
+            None => self.inner.select(epc),
+        }
+    }
+
+    fn read_alt(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        num_words: u8,
+        password: Option<Password>,
+    ) -> Gen2Result<Vec<u8>> {
+        match self.next_fault(false, false) {
+            Some(fault) => Err(fault.error()),
+            None => self.inner.read_alt(bank, address, num_words, password),
+        }
+    }
+
+    fn write(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        data: [u8; 2],
+        password: Option<Password>,
+    ) -> Gen2Result<()> {
+        match self.next_fault(true, false) {
+            Some(Fault::LostWriteReply) => {
+                self.inner.write(bank, address, data, password)?;
+                Err(Fault::LostWriteReply.error())
+            }
+            Some(Fault::DroppedWrite) => Ok(()),
+            Some(fault) => Err(fault.error()),
+            None => self.inner.write(bank, address, data, password),
+        }
+    }
+
+    fn lock(&mut self, payload: [u8; 3], password: Option<Password>) -> Gen2Result<()> {
+        match self.next_fault(false, false) {
+            Some(fault) => Err(fault.error()),
+            None => self.inner.lock(payload, password),
+        }
+    }
+
+    fn tune(&mut self, algorithm: TuningAlgorithm) -> Gen2Result<()> {
+        match self.next_fault(false, false) {
+            Some(fault) => Err(fault.error()),
+            None => self.inner.tune(algorithm),
+        }
+    }
+
+    fn reflected_power(&mut self, frequency: u32) -> Gen2Result<(i8, i8)> {
+        match self.next_fault(false, false) {
+            Some(fault) => Err(fault.error()),
+            None => self.inner.reflected_power(frequency),
+        }
+    }
+
+    fn adds_password(&self) -> bool {
+        self.inner.adds_password()
+    }
+}
+
+/// Retries a write that may fail while the tag is short on power, giving up
+/// after `attempts` tries with the last error.
+fn write_with_retry<R: Gen2Transport>(
+    reader: &mut R,
+    bank: MemoryBank,
+    address: u32,
+    data: [u8; 2],
+    attempts: u32,
+) -> Gen2Result<()> {
+    let mut result = reader.write(bank, address, data, None);
+    for _ in 1..attempts {
+        if result.is_ok() {
+            break;
+        }
+        result = reader.write(bank, address, data, None);
+    }
+    result
+}
+
+/// Source of time for the timing-dependent tag procedures, so tests can run
+/// them on virtual time instead of waiting.
+trait Clock {
+    /// Monotonic time since the clock was created.
+    fn elapsed(&self) -> std::time::Duration;
+
+    /// Wall-clock time, for log rows and sample timestamps.
+    fn now(&self) -> chrono::DateTime<chrono::Utc>;
+
+    fn sleep(&self, duration: std::time::Duration);
+}
+
+/// Real time, for use with hardware.
+struct SystemClock {
+    start: std::time::Instant,
+}
+
+impl SystemClock {
+    fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn elapsed(&self) -> std::time::Duration {
+        self.start.elapsed()
+    }
+
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        chrono::Utc::now()
+    }
+
+    fn sleep(&self, duration: std::time::Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Time that only moves when something sleeps on it or calls `advance`.
+struct VirtualClock {
+    start: chrono::DateTime<chrono::Utc>,
+    elapsed: std::cell::Cell<std::time::Duration>,
+}
+
+impl VirtualClock {
+    fn new(start: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            start,
+            elapsed: std::cell::Cell::new(std::time::Duration::ZERO),
+        }
+    }
+
+    fn advance(&self, duration: std::time::Duration) {
+        self.elapsed.set(self.elapsed.get() + duration);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn elapsed(&self) -> std::time::Duration {
+        self.elapsed.get()
+    }
+
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.start + chrono::Duration::from_std(self.elapsed.get()).unwrap()
+    }
+
+    fn sleep(&self, duration: std::time::Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Pause between the inventory rounds that keep a semi-passive tag powered
+/// while the ADXL363 measures.
+const FIELD_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(5);
+
+/// Timestamps of `count` FIFO samples taken at `odr_hz` after `start`, the
+/// first one a full period in. Worked out in nanoseconds, since most output
+/// data rates don't have a whole-millisecond period.
+fn sample_timestamps(
+    start: chrono::DateTime<chrono::Utc>,
+    odr_hz: f32,
+    count: usize,
+) -> Vec<chrono::DateTime<chrono::Utc>> {
+    let interval_ns = 1e9 / odr_hz as f64;
+    (0..count)
+        .map(|i| start + chrono::Duration::nanoseconds((interval_ns * (i + 1) as f64).round() as i64))
+        .collect()
+}
+
+/// Groups raw FIFO words into X, Y, Z samples by the axis tag in bits 15-14
+/// (0 X, 1 Y, 2 Z, 3 temperature). Temperature words are skipped and a
+/// partial sample, e.g. at either end of a FIFO that overflowed, is dropped.
+fn fifo_samples(words: &[u16]) -> Vec<[i16; 3]> {
+    let mut samples = Vec::new();
+    let mut sample = [0; 3];
+    let mut next_axis = 0;
+    for &word in words {
+        // the data is 14 bits, sign-extended into bits 13-12
+        let value = ((word << 2) as i16) >> 2;
+        match (word >> 14) as usize {
+            3 => {}
+            axis if axis == next_axis => {
+                sample[axis] = value;
+                next_axis += 1;
+                if next_axis == 3 {
+                    samples.push(sample);
+                    next_axis = 0;
+                }
+            }
+            0 => {
+                sample[0] = value;
+                next_axis = 1;
+            }
+            _ => next_axis = 0,
+        }
+    }
+    samples
+}
+
+fn fifo_words(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks_exact(2).map(|word| u16::from_le_bytes([word[0], word[1]])).collect()
+}
+
+const TEMP_SENSOR_CONTROL_WORD_1: u32 = 0xEC;
+const TEMP_SENSOR_CONTROL_WORD_2: u32 = 0xED;
+const TEMP_SENSOR_CONTROL_WORD_3: u32 = 0xEE;
+const CALIBRATION_WORD: u32 = 0xEF;
+const IO_CONTROL_WORD: u32 = 0xF0;
+const BATTERY_MANAGEMENT_WORD_1: u32 = 0xF1;
+const BATTERY_MANAGEMENT_WORD_2: u32 = 0xF2;
+const TOTAL_WORD: u32 = 0xF3;
+const SENSOR_DATA_MSW: u32 = 0x100;
+const SENSOR_DATA_LSW: u32 = 0x101;
+const IO_STATE_WORD: u32 = 0x10C;
+/// XPC indicator (XI) in the PC word, EPC bank word 1. The Gen2 air
+/// interface spec puts it at EPC memory bit 16h, counted from the word's MSB
+/// at 10h, which is bit 9 of the word as read. That the EM4325 answers with
+/// its sensor data words after the EPC when this bit is set is this file's
+/// assumption, not checked against the datasheet's reply format, and the
+/// Gen2 spec lets a tag with XPC words compute XI itself rather than store
+/// it. `simple_sensor_round` checks the replies for that reason.
+const PC_XI_BIT: u16 = 9;
+const BAP_MODE_WORD: u32 = 0x10D;
+
+fn bit(word: u16, n: u16) -> bool {
+    word & (1 << n) != 0
+}
+
+fn set_bit(value: bool, n: u16) -> u16 {
+    (value as u16) << n
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// Temperature sensor control words 0xEC-0xEE: alarm limits and monitoring.
+/// Limits use the same 9-bit two's complement code as the sensor data
+/// (0.25 °C per LSB).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct TempSensorControl {
+    /// 0xEC bit 15: raise the under-temperature alarm below `under_limit`.
+    under_alarm_enable: bool,
+    /// 0xEC bits 8-0.
+    under_limit: u16,
+    /// 0xED bit 15: raise the over-temperature alarm above `over_limit`.
+    over_alarm_enable: bool,
+    /// 0xED bits 8-0.
+    over_limit: u16,
+    /// 0xEE bit 15: sample the temperature periodically without a reader.
+    monitor_enable: bool,
+    /// 0xEE bits 14-12: consecutive out-of-limit samples before an alarm latches.
+    alarm_delay: u8,
+    /// 0xEE bits 7-0: minutes between monitoring samples.
+    sample_interval: u8,
+    /// Bits not covered above, one entry per word, kept so a decoded word
+    /// encodes back unchanged.
+    reserved: [u16; 3],
+}
+
+impl TempSensorControl {
+    const RESERVED: [u16; 3] = [0x7E00, 0x7E00, 0x0F00];
+
+    fn from_words(words: [u16; 3]) -> Self {
+        Self {
+            under_alarm_enable: bit(words[0], 15),
+            under_limit: words[0] & 0x1FF,
+            over_alarm_enable: bit(words[1], 15),
+            over_limit: words[1] & 0x1FF,
+            monitor_enable: bit(words[2], 15),
+            alarm_delay: (words[2] >> 12 & 0b111) as u8,
+            sample_interval: words[2] as u8,
+            reserved: [0, 1, 2].map(|i| words[i] & Self::RESERVED[i]),
+        }
+    }
+
+    fn to_words(&self) -> [u16; 3] {
+        [
+            set_bit(self.under_alarm_enable, 15) | self.under_limit & 0x1FF | self.reserved[0],
+            set_bit(self.over_alarm_enable, 15) | self.over_limit & 0x1FF | self.reserved[1],
+            set_bit(self.monitor_enable, 15)
+                | (self.alarm_delay as u16 & 0b111) << 12
+                | self.sample_interval as u16
+                | self.reserved[2],
+        ]
+    }
+}
+
+impl std::fmt::Display for TempSensorControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "under alarm {} ({} °C), over alarm {} ({} °C), monitoring {} every {} min, delay {}",
+            on_off(self.under_alarm_enable),
+            process_temp(self.under_limit),
+            on_off(self.over_alarm_enable),
+            process_temp(self.over_limit),
+            on_off(self.monitor_enable),
+            self.sample_interval,
+            self.alarm_delay
+        )
+    }
+}
+
+/// What the EM4325 I/O pins are used for.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum IoInterface {
+    #[default]
+    DiscreteIo,
+    SpiSlave,
+    SpiMaster,
+    SpiMasterAux,
+}
+
+/// I/O control word 0xF0.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct IoControl {
+    /// Bits 15-14.
+    interface: IoInterface,
+    /// Bit 13: connect the battery/storage capacitor to the supply (BAP switch).
+    bap_switch: bool,
+    /// Bits 11-8: pins P3-P0 driven as outputs (bit n is Pn).
+    pin_output_enable: u8,
+    /// Bits 7-4: level driven on each output pin.
+    pin_output_level: u8,
+    /// Bits 3-0: input pins that latch an event on a rising edge (tamper/aux).
+    pin_event_enable: u8,
+    /// Bit 12, unused.
+    reserved: u16,
+}
+
+impl IoControl {
+    fn from_word(word: u16) -> Self {
+        Self {
+            interface: match word >> 14 {
+                0 => IoInterface::DiscreteIo,
+                1 => IoInterface::SpiSlave,
+                2 => IoInterface::SpiMaster,
+                _ => IoInterface::SpiMasterAux,
+            },
+            bap_switch: bit(word, 13),
+            pin_output_enable: (word >> 8 & 0xF) as u8,
+            pin_output_level: (word >> 4 & 0xF) as u8,
+            pin_event_enable: (word & 0xF) as u8,
+            reserved: word & 0x1000,
+        }
+    }
+
+    fn to_word(&self) -> u16 {
+        (self.interface as u16) << 14
+            | set_bit(self.bap_switch, 13)
+            | (self.pin_output_enable as u16 & 0xF) << 8
+            | (self.pin_output_level as u16 & 0xF) << 4
+            | self.pin_event_enable as u16 & 0xF
+            | self.reserved
+    }
+}
+
+impl std::fmt::Display for IoControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?}, BAP switch {}, outputs {:04b} (levels {:04b}), event inputs {:04b}",
+            self.interface,
+            on_off(self.bap_switch),
+            self.pin_output_enable,
+            self.pin_output_level,
+            self.pin_event_enable
+        )
+    }
+}
+
+/// Battery management word 1 (0xF1).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct BatteryManagement1 {
+    /// Bit 13: watch the battery voltage and flag it when low.
+    battery_low_detect: bool,
+    /// Bit 0: battery management allowed, i.e. the tag may run battery assisted.
+    bap_allowed: bool,
+    /// Bits 15-14 and 12-1, unused.
+    reserved: u16,
+}
+
+impl BatteryManagement1 {
+    fn from_word(word: u16) -> Self {
+        Self {
+            battery_low_detect: bit(word, 13),
+            bap_allowed: bit(word, 0),
+            reserved: word & 0xDFFE,
+        }
+    }
+
+    fn to_word(&self) -> u16 {
+        set_bit(self.battery_low_detect, 13) | set_bit(self.bap_allowed, 0) | self.reserved
+    }
+}
+
+impl std::fmt::Display for BatteryManagement1 {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let allowed = if self.bap_allowed { "allowed" } else { "not allowed" };
+        write!(f, "battery mgmt {allowed}, battery-low detect {}", on_off(self.battery_low_detect))
+    }
+}
+
+/// Battery management word 2 (0xF2).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct BatteryManagement2 {
+    /// Bit 15 (read-only): a battery is connected.
+    battery_detected: bool,
+    /// Bit 14 (read-only): battery voltage below the low threshold.
+    battery_low: bool,
+    /// Bit 0: allow BAP to be enabled on command through the BAP mode word.
+    bap_on_command: bool,
+    /// Bits 13-1, unused.
+    reserved: u16,
+}
+
+impl BatteryManagement2 {
+    fn from_word(word: u16) -> Self {
+        Self {
+            battery_detected: bit(word, 15),
+            battery_low: bit(word, 14),
+            bap_on_command: bit(word, 0),
+            reserved: word & 0x3FFE,
+        }
+    }
+
+    fn to_word(&self) -> u16 {
+        set_bit(self.battery_detected, 15)
+            | set_bit(self.battery_low, 14)
+            | set_bit(self.bap_on_command, 0)
+            | self.reserved
+    }
+}
+
+impl std::fmt::Display for BatteryManagement2 {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let command = if self.bap_on_command { "allowed" } else { "not allowed" };
+        write!(
+            f,
+            "BAP on command {command}, battery {}{}",
+            if self.battery_detected { "detected" } else { "not detected" },
+            if self.battery_low { " (low)" } else { "" }
+        )
+    }
+}
+
+/// TOTAL word (0xF3): number of monitoring samples that fell outside the alarm
+/// limits. Writing zero resets it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct TotalWord {
+    count: u16,
+}
+
+impl TotalWord {
+    fn from_word(word: u16) -> Self {
+        Self { count: word }
+    }
+
+    fn to_word(&self) -> u16 {
+        self.count
+    }
+}
+
+impl std::fmt::Display for TotalWord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} out-of-limit samples", self.count)
+    }
+}
+
+/// BAP mode word (0x10D).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct BapMode {
+    /// Bit 0: run battery assisted. Only takes effect if battery management
+    /// word 2 allows BAP on command.
+    enabled: bool,
+    /// Bits 15-1, unused.
+    reserved: u16,
+}
+
+impl BapMode {
+    fn from_word(word: u16) -> Self {
+        Self {
+            enabled: bit(word, 0),
+            reserved: word & 0xFFFE,
+        }
+    }
+
+    fn to_word(&self) -> u16 {
+        set_bit(self.enabled, 0) | self.reserved
+    }
+}
+
+impl std::fmt::Display for BapMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "BAP {}", if self.enabled { "enabled" } else { "disabled" })
+    }
+}
+
+/// All EM4325 configuration words, decoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Em4325Config {
+    temp_sensor: TempSensorControl,
+    io: IoControl,
+    battery_1: BatteryManagement1,
+    battery_2: BatteryManagement2,
+    total: TotalWord,
+    bap_mode: BapMode,
+}
+
+impl Em4325Config {
+    /// Reads the configuration words from the selected tag.
+    fn read<R: Gen2Transport>(reader: &mut R) -> Gen2Result<Self> {
+        let words = |bytes: Vec<u8>| -> Vec<u16> {
+            bytes.chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect()
+        };
+
+        let temp = words(reader.read_alt(MemoryBank::User, TEMP_SENSOR_CONTROL_WORD_1, 3, None)?);
+        let control = words(reader.read_alt(MemoryBank::User, IO_CONTROL_WORD, 4, None)?);
+        let bap_mode = words(reader.read_alt(MemoryBank::User, BAP_MODE_WORD, 1, None)?);
+
+        Ok(Self {
+            temp_sensor: TempSensorControl::from_words([temp[0], temp[1], temp[2]]),
+            io: IoControl::from_word(control[0]),
+            battery_1: BatteryManagement1::from_word(control[1]),
+            battery_2: BatteryManagement2::from_word(control[2]),
+            total: TotalWord::from_word(control[3]),
+            bap_mode: BapMode::from_word(bap_mode[0]),
+        })
+    }
+}
+
+impl std::fmt::Display for Em4325Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Temp Sensor Control: {}", self.temp_sensor)?;
+        writeln!(f, "I/O Control: {}", self.io)?;
+        writeln!(f, "Battery Management 1: {}", self.battery_1)?;
+        writeln!(f, "Battery Management 2: {}", self.battery_2)?;
+        writeln!(f, "TOTAL: {}", self.total)?;
+        write!(f, "BAP Mode: {}", self.bap_mode)
+    }
+}
+
+impl Em4325Config {
+    /// Bits the chip sets itself; a write can't change them.
+    const READ_ONLY: [(u32, u16); 1] = [(BATTERY_MANAGEMENT_WORD_2, 0xC000)];
+    /// Words the chip counts up in while it runs, so a difference in them
+    /// isn't configuration drift.
+    const COUNTERS: [u32; 1] = [TOTAL_WORD];
+
+    /// Every configuration word with its address, in memory order.
+    fn words(&self) -> [(u32, u16); 8] {
+        let temp = self.temp_sensor.to_words();
+        [
+            (TEMP_SENSOR_CONTROL_WORD_1, temp[0]),
+            (TEMP_SENSOR_CONTROL_WORD_2, temp[1]),
+            (TEMP_SENSOR_CONTROL_WORD_3, temp[2]),
+            (IO_CONTROL_WORD, self.io.to_word()),
+            (BATTERY_MANAGEMENT_WORD_1, self.battery_1.to_word()),
+            (BATTERY_MANAGEMENT_WORD_2, self.battery_2.to_word()),
+            (TOTAL_WORD, self.total.to_word()),
+            (BAP_MODE_WORD, self.bap_mode.to_word()),
+        ]
+    }
+
+    fn from_words(words: &[(u32, u16)]) -> Self {
+        let word = |address| words.iter().find(|(a, _)| *a == address).map_or(0, |(_, w)| *w);
+        Self {
+            temp_sensor: TempSensorControl::from_words([
+                word(TEMP_SENSOR_CONTROL_WORD_1),
+                word(TEMP_SENSOR_CONTROL_WORD_2),
+                word(TEMP_SENSOR_CONTROL_WORD_3),
+            ]),
+            io: IoControl::from_word(word(IO_CONTROL_WORD)),
+            battery_1: BatteryManagement1::from_word(word(BATTERY_MANAGEMENT_WORD_1)),
+            battery_2: BatteryManagement2::from_word(word(BATTERY_MANAGEMENT_WORD_2)),
+            total: TotalWord::from_word(word(TOTAL_WORD)),
+            bap_mode: BapMode::from_word(word(BAP_MODE_WORD)),
+        }
+    }
+
+    fn writable_mask(address: u32) -> u16 {
+        Self::READ_ONLY
+            .iter()
+            .find(|(a, _)| *a == address)
+            .map_or(0xFFFF, |(_, bits)| !bits)
+    }
+}
+
+/// One word write in a configuration sequence.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ConfigStep {
+    name: &'static str,
+    address: u32,
+    value: u16,
+}
+
+/// Named EM4325 operating modes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Em4325Profile {
+    /// Passive operation with BAP available on command (em_write_config).
+    Default,
+    /// Battery assisted (em_bap_mode).
+    Bap,
+    /// Passive with BAP control disabled again after setup (em_passive_mode).
+    Passive,
+    /// Passive, with BAP allowed on command so pseudo-BAP measurements can
+    /// switch it on and off (em_pseudo_bap_mode).
+    PseudoBap,
+}
+
+impl Em4325Profile {
+    /// The word writes for this profile, in the order the chip requires. BAP
+    /// control in battery management word 2 has to be allowed while the BAP
+    /// mode word is written, which is why passive mode clears it afterwards.
+    ///
+    /// Every profile zeroes the temperature sensor control words and the TOTAL
+    /// word, which turns off any alarms and monitoring set with
+    /// `set_temperature_alarms` and resets the out-of-limit count. Set the
+    /// alarms again after applying a profile.
+    fn steps(self) -> Vec<ConfigStep> {
+        let (io, battery_1, bap_mode) = match self {
+            Em4325Profile::Default | Em4325Profile::PseudoBap => (0x0600, 0x0000, 0x0000),
+            Em4325Profile::Bap => (0xE000, 0x2001, 0x0001),
+            Em4325Profile::Passive => (0xE600, 0x0000, 0x0000),
+        };
+
+        let step = |name, address, value| ConfigStep { name, address, value };
+        let mut steps = vec![
+            step("temp sensor control word 1", TEMP_SENSOR_CONTROL_WORD_1, 0x0000),
+            step("temp sensor control word 2", TEMP_SENSOR_CONTROL_WORD_2, 0x0000),
+            step("temp sensor control word 3", TEMP_SENSOR_CONTROL_WORD_3, 0x0000),
+            step("IO control word", IO_CONTROL_WORD, io),
+            step("battery management word 1", BATTERY_MANAGEMENT_WORD_1, battery_1),
+            step("battery management word 2", BATTERY_MANAGEMENT_WORD_2, 0x0001),
+            step("TOTAL word", TOTAL_WORD, 0x0000),
+            step("BAP mode word", BAP_MODE_WORD, bap_mode),
+        ];
+        if self == Em4325Profile::Passive {
+            // disable BAP control after writing BAP mode word
+            steps.push(step("battery management word 2 rewrite", BATTERY_MANAGEMENT_WORD_2, 0x0000));
+        }
+        steps
+    }
+
+    /// The configuration the tag is in once every step has been written.
+    fn config(self) -> Em4325Config {
+        let mut words = Vec::new();
+        for step in self.steps() {
+            words.retain(|(a, _)| *a != step.address);
+            words.push((step.address, step.value));
+        }
+        Em4325Config::from_words(&words)
+    }
+}
+
+/// What went wrong while applying a configuration.
+#[derive(Debug)]
+enum ConfigFailure {
+    Write(StuhflError),
+    ReadBack(StuhflError),
+    Mismatch { address: u32, expected: u16, found: u16 },
+}
+
+/// A configuration sequence that did not complete. `written` lists the steps
+/// that were acknowledged before the failure and `state` is the configuration
+/// read back afterwards, if the tag still answered.
+#[derive(Debug)]
+struct ConfigError {
+    step: &'static str,
+    failure: ConfigFailure,
+    written: Vec<ConfigStep>,
+    state: Option<Em4325Config>,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.failure {
+            ConfigFailure::Write(err) => write!(f, "writing {} failed: {err:?}", self.step)?,
+            ConfigFailure::ReadBack(err) => write!(f, "reading back {} failed: {err:?}", self.step)?,
+            ConfigFailure::Mismatch { address, expected, found } => write!(
+                f,
+                "{} (0x{address:X}) reads back 0x{found:04X}, expected 0x{expected:04X}",
+                self.step
+            )?,
+        }
+        write!(f, " after {} of the steps were written", self.written.len())?;
+        match &self.state {
+            Some(state) => write!(f, "; tag was left as:\n{state}"),
+            None => write!(f, "; tag state unknown"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Writes `steps` in order to the selected tag, then reads the configuration
+/// back and checks every word the steps touched.
+fn apply_config<R: Gen2Transport>(
+    reader: &mut R,
+    steps: &[ConfigStep],
+) -> Result<Em4325Config, ConfigError> {
+    let mut written = Vec::new();
+    let fail = |reader: &mut R, step, failure, written| ConfigError {
+        step,
+        failure,
+        written,
+        state: Em4325Config::read(reader).ok(),
+    };
+
+    for step in steps {
+        if let Err(err) = reader.write(MemoryBank::User, step.address, step.value.to_be_bytes(), None) {
+            return Err(fail(reader, step.name, ConfigFailure::Write(err), written));
+        }
+        written.push(*step);
+    }
+
+    let config = match Em4325Config::read(reader) {
+        Ok(config) => config,
+        Err(err) => return Err(fail(reader, "configuration", ConfigFailure::ReadBack(err), written)),
+    };
+
+    let words = config.words();
+    for (i, step) in steps.iter().enumerate() {
+        // the last step for an address is what it should hold
+        if steps[i + 1..].iter().any(|s| s.address == step.address) {
+            continue;
+        }
+        let address = step.address;
+        let found = match words.iter().find(|(a, _)| *a == address) {
+            Some(&(_, found)) => found,
+            // words outside the configuration block, e.g. calibration
+            None => match read_word(reader, address) {
+                Ok(found) => found,
+                Err(err) => return Err(fail(reader, step.name, ConfigFailure::ReadBack(err), written)),
+            },
+        };
+        let mask = Em4325Config::writable_mask(address);
+        if found & mask != step.value & mask {
+            let failure = ConfigFailure::Mismatch {
+                address,
+                expected: step.value,
+                found,
+            };
+            return Err(ConfigError {
+                step: step.name,
+                failure,
+                written,
+                state: Some(config),
+            });
+        }
+    }
+
+    Ok(config)
+}
+
+/// Outcome of restoring the snapshot after a failed transaction.
+#[derive(Debug)]
+enum Rollback {
+    /// Every word reads back as it was before the transaction.
+    Restored,
+    /// Restoring stopped; `unrestored` are the addresses that may still hold
+    /// new values.
+    Failed {
+        address: u32,
+        error: StuhflError,
+        unrestored: Vec<u32>,
+    },
+    /// Every word was written back but `address` reads back differently.
+    Mismatch { address: u32, expected: u16, found: u16 },
+}
+
+/// A configuration transaction that failed. `snapshot` is what the words held
+/// beforehand; `rollback` is `None` if the snapshot itself couldn't be taken,
+/// in which case nothing was written.
+#[derive(Debug)]
+struct TransactionError {
+    error: ConfigError,
+    snapshot: Vec<(u32, u16)>,
+    rollback: Option<Rollback>,
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", self.error)?;
+        match &self.rollback {
+            None => write!(f, "nothing was written"),
+            Some(Rollback::Restored) => write!(f, "rolled back to the previous configuration"),
+            Some(Rollback::Failed { address, error, unrestored }) => write!(
+                f,
+                "rollback failed restoring 0x{address:X}: {error:?}; still changed: {unrestored:X?}"
+            ),
+            Some(Rollback::Mismatch { address, expected, found }) => write!(
+                f,
+                "rollback incomplete: 0x{address:X} reads 0x{found:04X}, expected 0x{expected:04X}"
+            ),
+        }
+    }
+}
+
+impl Error for TransactionError {}
+
+fn read_word<R: Gen2Transport>(reader: &mut R, address: u32) -> Gen2Result<u16> {
+    let bytes = reader.read_alt(MemoryBank::User, address, 1, None)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Reads the current value of every word `steps` touch, in first-write order.
+fn snapshot_words<R: Gen2Transport>(reader: &mut R, steps: &[ConfigStep]) -> Gen2Result<Vec<(u32, u16)>> {
+    let mut snapshot: Vec<(u32, u16)> = Vec::new();
+    for step in steps {
+        if snapshot.iter().all(|(a, _)| *a != step.address) {
+            snapshot.push((step.address, read_word(reader, step.address)?));
+        }
+    }
+    Ok(snapshot)
+}
+
+/// The writes that put `snapshot` back. The BAP mode word is restored between
+/// a BM2 write that allows BAP control and the final BM2 value, so the BAP
+/// write never lands while BM2 disallows BAP control; whether the chip
+/// refuses such a write isn't established, so this only avoids the case.
+fn restore_sequence(snapshot: &[(u32, u16)]) -> Vec<(u32, u16)> {
+    let find = |address| snapshot.iter().find(|(a, _)| *a == address).map(|&(_, v)| v);
+    let mut sequence: Vec<(u32, u16)> = snapshot
+        .iter()
+        .filter(|(a, _)| *a != BATTERY_MANAGEMENT_WORD_2 && *a != BAP_MODE_WORD)
+        .copied()
+        .collect();
+    let battery_2 = find(BATTERY_MANAGEMENT_WORD_2);
+    if let Some(bap_mode) = find(BAP_MODE_WORD) {
+        if let Some(battery_2) = battery_2 {
+            sequence.push((BATTERY_MANAGEMENT_WORD_2, battery_2 | 0x0001));
+        }
+        sequence.push((BAP_MODE_WORD, bap_mode));
+    }
+    if let Some(battery_2) = battery_2 {
+        sequence.push((BATTERY_MANAGEMENT_WORD_2, battery_2));
+    }
+    sequence
+}
+
+/// Writes the snapshot back, BAP mode word included, and checks each word.
+fn restore_words<R: Gen2Transport>(reader: &mut R, snapshot: &[(u32, u16)]) -> Rollback {
+    let sequence = restore_sequence(snapshot);
+    for (i, &(address, value)) in sequence.iter().enumerate() {
+        if let Err(error) = write_with_retry(reader, MemoryBank::User, address, value.to_be_bytes(), 3) {
+            let mut unrestored: Vec<u32> = Vec::new();
+            for &(a, _) in &sequence[i..] {
+                if !unrestored.contains(&a) {
+                    unrestored.push(a);
+                }
+            }
+            return Rollback::Failed {
+                address,
+                error,
+                unrestored,
+            };
+        }
+    }
+    for &(address, expected) in snapshot {
+        let mask = Em4325Config::writable_mask(address);
+        match read_word(reader, address) {
+            Ok(found) if found & mask == expected & mask => {}
+            Ok(found) => return Rollback::Mismatch { address, expected, found },
+            Err(error) => {
+                return Rollback::Failed {
+                    address,
+                    error,
+                    unrestored: Vec::new(),
+                }
+            }
+        }
+    }
+    Rollback::Restored
+}
+
+/// Applies `steps` as a transaction: snapshot the words they touch, write and
+/// verify, and on any failure write the snapshot back before returning.
+fn apply_config_transaction<R: Gen2Transport>(
+    reader: &mut R,
+    steps: &[ConfigStep],
+) -> Result<Em4325Config, TransactionError> {
+    let snapshot = match snapshot_words(reader, steps) {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            let error = ConfigError {
+                step: "snapshot",
+                failure: ConfigFailure::ReadBack(err),
+                written: Vec::new(),
+                state: None,
+            };
+            return Err(TransactionError {
+                error,
+                snapshot: Vec::new(),
+                rollback: None,
+            });
+        }
+    };
+
+    apply_config(reader, steps).map_err(|error| {
+        // even the first write may have landed if only its reply was lost
+        let rollback = Some(restore_words(reader, &snapshot));
+        TransactionError {
+            error,
+            snapshot,
+            rollback,
+        }
+    })
+}
+
+/// Puts the selected tag into `profile` and confirms it by reading back,
+/// restoring the previous configuration if any step fails.
+fn apply_profile<R: Gen2Transport>(
+    reader: &mut R,
+    profile: Em4325Profile,
+) -> Result<Em4325Config, TransactionError> {
+    apply_config_transaction(reader, &profile.steps())
+}
+
+impl Em4325Config {
+    /// Every named field with the address of the word it lives in. Read-only
+    /// status bits are left out since a profile can't set them.
+    fn fields(&self) -> Vec<(u32, &'static str, u16)> {
+        let t = &self.temp_sensor;
+        vec![
+            (TEMP_SENSOR_CONTROL_WORD_1, "under alarm enable", t.under_alarm_enable as u16),
+            (TEMP_SENSOR_CONTROL_WORD_1, "under limit", t.under_limit),
+            (TEMP_SENSOR_CONTROL_WORD_2, "over alarm enable", t.over_alarm_enable as u16),
+            (TEMP_SENSOR_CONTROL_WORD_2, "over limit", t.over_limit),
+            (TEMP_SENSOR_CONTROL_WORD_3, "monitor enable", t.monitor_enable as u16),
+            (TEMP_SENSOR_CONTROL_WORD_3, "alarm delay", t.alarm_delay as u16),
+            (TEMP_SENSOR_CONTROL_WORD_3, "sample interval", t.sample_interval as u16),
+            (IO_CONTROL_WORD, "interface", self.io.interface as u16),
+            (IO_CONTROL_WORD, "BAP switch", self.io.bap_switch as u16),
+            (IO_CONTROL_WORD, "pin output enable", self.io.pin_output_enable as u16),
+            (IO_CONTROL_WORD, "pin output level", self.io.pin_output_level as u16),
+            (IO_CONTROL_WORD, "pin event enable", self.io.pin_event_enable as u16),
+            (BATTERY_MANAGEMENT_WORD_1, "battery-low detect", self.battery_1.battery_low_detect as u16),
+            (BATTERY_MANAGEMENT_WORD_1, "battery mgmt allowed", self.battery_1.bap_allowed as u16),
+            (BATTERY_MANAGEMENT_WORD_2, "BAP on command", self.battery_2.bap_on_command as u16),
+            (TOTAL_WORD, "out-of-limit count", self.total.count),
+            (BAP_MODE_WORD, "BAP enabled", self.bap_mode.enabled as u16),
+        ]
+    }
+}
+
+fn word_name(address: u32) -> &'static str {
+    match address {
+        TEMP_SENSOR_CONTROL_WORD_1 => "Temp sensor control 1",
+        TEMP_SENSOR_CONTROL_WORD_2 => "Temp sensor control 2",
+        TEMP_SENSOR_CONTROL_WORD_3 => "Temp sensor control 3",
+        IO_CONTROL_WORD => "IO control",
+        BATTERY_MANAGEMENT_WORD_1 => "Battery management 1",
+        BATTERY_MANAGEMENT_WORD_2 => "Battery management 2",
+        TOTAL_WORD => "TOTAL",
+        CALIBRATION_WORD => "Temp sensor calibration",
+        BAP_MODE_WORD => "BAP mode",
+        _ => "User word",
+    }
+}
+
+/// A configuration word that differs from the target, with the fields that
+/// account for the difference. Reserved bits can differ with no field listed.
+#[derive(Debug, PartialEq)]
+struct WordDiff {
+    address: u32,
+    expected: u16,
+    found: u16,
+    fields: Vec<&'static str>,
+}
+
+impl std::fmt::Display for WordDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: expected 0x{:04X}, found 0x{:04X}",
+            word_name(self.address),
+            self.expected,
+            self.found
+        )?;
+        match self.fields.len() {
+            0 => write!(f, " — reserved bits differ"),
+            1 => write!(f, " — {} differs", self.fields[0]),
+            _ => write!(f, " — {} differ", self.fields.join(", ")),
+        }
+    }
+}
+
+/// Compares a tag's configuration with the target, word by word. Status bits
+/// and counters the chip updates itself are not compared.
+fn diff_config(expected: &Em4325Config, found: &Em4325Config) -> Vec<WordDiff> {
+    let expected_fields = expected.fields();
+    let found_fields = found.fields();
+
+    expected
+        .words()
+        .iter()
+        .zip(found.words())
+        .filter(|(word, _)| !Em4325Config::COUNTERS.contains(&word.0))
+        .filter_map(|(&(address, expected), (_, found))| {
+            let mask = Em4325Config::writable_mask(address);
+            if expected & mask == found & mask {
+                return None;
+            }
+            let fields = expected_fields
+                .iter()
+                .zip(&found_fields)
+                .filter(|(e, f)| e.0 == address && e.2 != f.2)
+                .map(|(e, _)| e.1)
+                .collect();
+            Some(WordDiff {
+                address,
+                expected,
+                found,
+                fields,
+            })
+        })
+        .collect()
+}
+
+/// Selects `epc` and compares its configuration with `profile` without
+/// writing anything.
+fn audit_tag<R: Gen2Transport>(
+    reader: &mut R,
+    epc: &HexID,
+    profile: Em4325Profile,
+) -> Gen2Result<Vec<WordDiff>> {
+    reader.select(epc)?;
+    let found = Em4325Config::read(reader)?;
+    Ok(diff_config(&profile.config(), &found))
+}
+
+/// Saved configuration of one tag: every configuration word plus the
+/// calibration word, with the EPC and TID it came from. The EPC only records
+/// where the snapshot was taken; restoring never writes it.
+#[derive(Debug, PartialEq)]
+struct TagSnapshot {
+    epc: String,
+    tid: String,
+    /// When the snapshot was taken, RFC 3339.
+    taken: String,
+    words: Vec<(u32, u16)>,
+}
+
+impl TagSnapshot {
+    const VERSION: u32 = 1;
+
+    /// Selects `epc` and reads its configuration.
+    fn take<R: Gen2Transport>(reader: &mut R, clock: &dyn Clock, epc: &HexID) -> Gen2Result<Self> {
+        reader.select(epc)?;
+        let tid = reader.read_alt(MemoryBank::Tid, 0, 6, None)?;
+        let mut words = Em4325Config::read(reader)?.words().to_vec();
+        words.insert(3, (CALIBRATION_WORD, read_word(reader, CALIBRATION_WORD)?));
+
+        Ok(Self {
+            epc: to_hex(&hex_bytes(epc)),
+            tid: to_hex(&tid),
+            taken: clock.now().to_rfc3339(),
+            words,
+        })
+    }
+
+    fn to_json(&self) -> String {
+        let words: Vec<serde_json::Value> = self
+            .words
+            .iter()
+            .map(|(address, value)| serde_json::json!({"address": address, "value": value}))
+            .collect();
+        let json = serde_json::json!({
+            "version": Self::VERSION,
+            "epc": self.epc,
+            "tid": self.tid,
+            "taken": self.taken,
+            "words": words,
+        });
+        serde_json::to_string_pretty(&json).expect("a JSON value always serializes") + "\n"
+    }
+
+    /// Parses the JSON written by `to_json`, rejecting other versions and
+    /// word entries that don't fit an address or a 16-bit value.
+    fn from_json(json: &str) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let json: serde_json::Value = serde_json::from_str(json).map_err(|err| invalid(err.to_string()))?;
+
+        let version = json["version"]
+            .as_u64()
+            .ok_or_else(|| invalid("missing \"version\"".to_string()))?;
+        if version != u64::from(Self::VERSION) {
+            return Err(invalid(format!("unsupported snapshot version {version}")));
+        }
+
+        let text = |key: &str| {
+            json[key]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| invalid(format!("missing \"{key}\"")))
+        };
+        let words = json["words"]
+            .as_array()
+            .ok_or_else(|| invalid("missing \"words\"".to_string()))?
+            .iter()
+            .map(|entry| {
+                let address = entry["address"].as_u64().and_then(|a| u32::try_from(a).ok());
+                let value = entry["value"].as_u64().and_then(|v| u16::try_from(v).ok());
+                match (address, value) {
+                    (Some(address), Some(value)) => Ok((address, value)),
+                    _ => Err(invalid(format!("bad word entry {entry}"))),
+                }
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            epc: text("epc")?,
+            tid: text("tid")?,
+            taken: text("taken")?,
+            words,
+        })
+    }
+
+    fn save(&self, path: impl AsRef<std::path::Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+
+    fn load(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Writes the snapshot onto `epc` as a transaction. The calibration word
+    /// belongs to the chip it was trimmed on, so it is only restored when the
+    /// target has the same TID. The target keeps its own EPC.
+    fn restore<R: Gen2Transport>(
+        &self,
+        reader: &mut R,
+        epc: &HexID,
+    ) -> Result<RestoredSnapshot, Box<dyn Error>> {
+        reader.select(epc)?;
+        let same_chip = to_hex(&reader.read_alt(MemoryBank::Tid, 0, 6, None)?) == self.tid;
+
+        let words: Vec<(u32, u16)> = self
+            .words
+            .iter()
+            .filter(|(address, _)| same_chip || *address != CALIBRATION_WORD)
+            .copied()
+            .collect();
+        let steps: Vec<ConfigStep> = restore_sequence(&words)
+            .into_iter()
+            .map(|(address, value)| ConfigStep {
+                name: word_name(address),
+                address,
+                value,
+            })
+            .collect();
+
+        Ok(RestoredSnapshot {
+            config: apply_config_transaction(reader, &steps)?,
+            calibration_restored: same_chip,
+        })
+    }
+}
+
+/// What `TagSnapshot::restore` left on the target tag.
+#[derive(Clone, Debug, PartialEq)]
+struct RestoredSnapshot {
+    config: Em4325Config,
+    /// False when the target is a different chip and kept its own
+    /// calibration word.
+    calibration_restored: bool,
+}
+
+/// Sensor data words 0x100-0x101, decoded. Reading them makes the tag take a
+/// fresh measurement; `valid` is clear when it couldn't, in which case the
+/// temperature is whatever was left from before.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct SensorData {
+    /// 0x100 bits 8-0, in °C.
+    temperature: f32,
+    /// 0x100 bit 9: the temperature comes from a completed measurement.
+    valid: bool,
+    /// 0x100 bit 10: unattended monitoring is running.
+    monitor_active: bool,
+    /// 0x100 bit 12: the battery is below the low-battery threshold.
+    battery_low: bool,
+    /// 0x100 bit 13: latched under-temperature alarm.
+    under_temp_alarm: bool,
+    /// 0x100 bit 14: latched over-temperature alarm.
+    over_temp_alarm: bool,
+    /// 0x100 bit 15: an event was seen on the aux (I/O) pins.
+    aux_event: bool,
+    /// 0x101: measurement counter, bumped on every measurement. The EM4325
+    /// has no real-time clock, so this is the only ordering there is.
+    sequence: u16,
+}
+
+impl SensorData {
+    fn from_words(msw: u16, lsw: u16) -> Self {
+        Self {
+            temperature: process_temp(msw & 0x1FF),
+            valid: bit(msw, 9),
+            monitor_active: bit(msw, 10),
+            battery_low: bit(msw, 12),
+            under_temp_alarm: bit(msw, 13),
+            over_temp_alarm: bit(msw, 14),
+            aux_event: bit(msw, 15),
+            sequence: lsw,
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self::from_words(
+            u16::from_be_bytes([bytes[0], bytes[1]]),
+            u16::from_be_bytes([bytes[2], bytes[3]]),
+        )
+    }
+
+    /// True if this is a valid measurement taken after `previous`. The counter
+    /// wraps, so anything up to half its range ahead counts as newer.
+    fn is_fresh_since(&self, previous: &SensorData) -> bool {
+        let ahead = self.sequence.wrapping_sub(previous.sequence);
+        self.valid && ahead != 0 && ahead < 0x8000
+    }
+}
+
+impl std::fmt::Display for SensorData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} °C (#{}", self.temperature, self.sequence)?;
+        if !self.valid {
+            write!(f, ", stale")?;
+        }
+        let flags = [
+            (self.under_temp_alarm, "under alarm"),
+            (self.over_temp_alarm, "over alarm"),
+            (self.battery_low, "battery low"),
+            (self.aux_event, "aux event"),
+            (self.monitor_active, "monitoring"),
+        ];
+        for (_, name) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, ", {name}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Reads and decodes both sensor data words from the selected tag. Every
+/// sensor read in this file goes through here rather than
+/// `tag_sensors::get_sensor_data`, which only takes a `Gen2Reader` and keeps
+/// just the temperature.
+fn read_sensor_data<R: Gen2Transport>(reader: &mut R) -> Gen2Result<SensorData> {
+    let bytes = reader.read_alt(MemoryBank::User, SENSOR_DATA_MSW, 2, None)?;
+    Ok(SensorData::from_bytes(&bytes))
+}
+
+/// Takes a fresh measurement on the selected tag and returns the temperature.
+fn read_temperature<R: Gen2Transport>(reader: &mut R) -> Gen2Result<f32> {
+    Ok(read_sensor_data(reader)?.temperature)
+}
+
+/// Mean of `samples` temperature readings spaced `interval` apart.
+fn mean_temperature<R: Gen2Transport>(
+    reader: &mut R,
+    clock: &dyn Clock,
+    samples: usize,
+    interval: std::time::Duration,
+) -> Gen2Result<f32> {
+    let mut sum = 0.0;
+    for i in 0..samples {
+        if i > 0 {
+            clock.sleep(interval);
+        }
+        sum += read_temperature(reader)?;
+    }
+    Ok(sum / samples as f32)
+}
+
+/// Fine trim field (bits 4-0 of the calibration word) as a signed step count.
+fn fine_trim_steps(word: u16) -> i16 {
+    let trim = (word & 0x1F) as i16;
+    if trim & 0x10 != 0 {
+        trim - 0x20
+    } else {
+        trim
+    }
+}
+
+/// Tag reading against a trusted thermometer at one reference temperature.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CalibrationPoint {
+    reference: f32,
+    measured: f32,
+}
+
+/// Before and after values of one calibration.
+#[derive(Debug, PartialEq)]
+struct CalibrationRecord {
+    epc: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    points: Vec<CalibrationPoint>,
+    word_before: u16,
+    word_after: u16,
+    /// Mean reading at the last reference once the new trim is in place.
+    remeasured: f32,
+}
+
+impl CalibrationRecord {
+    /// Remaining error at the last reference point, in °C.
+    fn residual(&self) -> f32 {
+        self.remeasured - self.points[self.points.len() - 1].reference
+    }
+
+    fn confirmed(&self) -> bool {
+        self.residual().abs() <= CALIBRATION_TOLERANCE
+    }
+
+    /// Appends the record to a CSV log, writing the header first for a new file.
+    fn log(&self, path: impl AsRef<std::path::Path>) -> io::Result<()> {
+        let mut csv_file = OpenOptions::new().append(true).create(true).open(path)?;
+        if csv_file.metadata()?.len() == 0 {
+            writeln!(
+                &mut csv_file,
+                "Timestamp, EPC, References (Celsius), Measured (Celsius), Word Before, Word After, Remeasured (Celsius)"
+            )?;
+        }
+        let column = |f: fn(&CalibrationPoint) -> f32| {
+            self.points.iter().map(|p| f(p).to_string()).collect::<Vec<_>>().join(" ")
+        };
+        writeln!(
+            &mut csv_file,
+            "{}, {}, {}, {}, 0x{:04X}, 0x{:04X}, {}",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            self.epc,
+            column(|p| p.reference),
+            column(|p| p.measured),
+            self.word_before,
+            self.word_after,
+            self.remeasured
+        )
+    }
+}
+
+/// Readings per calibration point and for the confirmation measurement.
+const CALIBRATION_SAMPLES: usize = 8;
+/// Largest remaining error after trimming that still counts as calibrated, °C.
+const CALIBRATION_TOLERANCE: f32 = 0.5;
+
+/// Averages readings from the selected tag, which must be sitting at
+/// `reference` °C as measured by a trusted thermometer.
+fn measure_calibration_point<R: Gen2Transport>(
+    reader: &mut R,
+    clock: &dyn Clock,
+    reference: f32,
+) -> Gen2Result<CalibrationPoint> {
+    let measured = mean_temperature(reader, clock, CALIBRATION_SAMPLES, std::time::Duration::from_millis(200))?;
+    Ok(CalibrationPoint { reference, measured })
+}
+
+/// Trims the selected tag's temperature sensor from one or more reference
+/// points: the mean error over the points becomes a fine-trim correction in
+/// 0.25 °C steps, written into user word 0xEF. The tag is then re-measured at
+/// the last reference point to confirm the correction.
+fn calibrate_temperature<R: Gen2Transport>(
+    reader: &mut R,
+    clock: &dyn Clock,
+    epc: &HexID,
+    points: &[CalibrationPoint],
+) -> Result<CalibrationRecord, Box<dyn Error>> {
+    if points.is_empty() {
+        return Err("calibration needs at least one reference point".into());
+    }
+
+    reader.select(epc)?;
+    let factory = u16::from_be_bytes(reader.read_alt(MemoryBank::Tid, 0x0D, 1, None)?[..2].try_into()?);
+    let word_before = read_word(reader, CALIBRATION_WORD)?;
+
+    // highest 11 bits must be identical
+    if factory & 0xFFE0 != word_before & 0xFFE0 {
+        return Err("calibration word doesn't match the TID copy, run em_verify_calibration first".into());
+    }
+
+    let error = points.iter().map(|p| p.reference - p.measured).sum::<f32>() / points.len() as f32;
+    let trim = fine_trim_steps(word_before) + (error / 0.25).round() as i16;
+    if !(-16..=15).contains(&trim) {
+        return Err(format!("correction of {error:.2} °C is outside the fine trim range").into());
+    }
+    let word_after = word_before & 0xFFE0 | trim as u16 & 0x1F;
+
+    let step = ConfigStep {
+        name: word_name(CALIBRATION_WORD),
+        address: CALIBRATION_WORD,
+        value: word_after,
+    };
+    apply_config_transaction(reader, &[step])?;
+
+    let last = points[points.len() - 1];
+    let remeasured = measure_calibration_point(reader, clock, last.reference)?.measured;
+
+    Ok(CalibrationRecord {
+        epc: to_hex(&hex_bytes(epc)),
+        timestamp: clock.now(),
+        points: points.to_vec(),
+        word_before,
+        word_after,
+        remeasured,
+    })
+}
+
+/// Converts °C to the 9-bit two's complement code used by the sensor data and
+/// the alarm limits, rounding to the nearest 0.25 °C. `None` if the chip can't
+/// represent it.
+fn temp_code(celsius: f32) -> Option<u16> {
+    let code = (celsius * 4.0).round();
+    if !(-255.0..=255.0).contains(&code) {
+        return None;
+    }
+    Some(code as i16 as u16 & 0x1FF)
+}
+
+/// On-chip temperature alarm settings in °C, as they go into the temperature
+/// sensor control words.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct TemperatureAlarms {
+    /// Alarm when the temperature falls below this.
+    under: Option<f32>,
+    /// Alarm when the temperature rises above this.
+    over: Option<f32>,
+    /// Minutes between samples taken without a reader; 0 turns monitoring off.
+    sample_interval: u8,
+    /// Consecutive out-of-limit samples ignored before an alarm latches (0-7).
+    alarm_delay: u8,
+}
+
+impl TemperatureAlarms {
+    /// Encodes the alarms over `current`, the control words as read from the
+    /// tag, so the bits these settings don't cover keep their value.
+    fn to_control(self, current: &TempSensorControl) -> Result<TempSensorControl, String> {
+        let limit = |celsius: Option<f32>| match celsius {
+            Some(celsius) => temp_code(celsius)
+                .map(|code| (true, code))
+                .ok_or(format!("{celsius} °C is outside the sensor range of ±63.75 °C")),
+            None => Ok((false, 0)),
+        };
+        if let (Some(under), Some(over)) = (self.under, self.over) {
+            if under >= over {
+                return Err(format!("under limit {under} °C is not below over limit {over} °C"));
+            }
+        }
+        if self.alarm_delay > 7 {
+            return Err(format!("alarm delay {} is more than 7 samples", self.alarm_delay));
+        }
+
+        let (under_alarm_enable, under_limit) = limit(self.under)?;
+        let (over_alarm_enable, over_limit) = limit(self.over)?;
+        Ok(TempSensorControl {
+            under_alarm_enable,
+            under_limit,
+            over_alarm_enable,
+            over_limit,
+            monitor_enable: self.sample_interval > 0,
+            alarm_delay: self.alarm_delay,
+            sample_interval: self.sample_interval,
+            reserved: current.reserved,
+        })
+    }
+
+    fn from_control(control: &TempSensorControl) -> Self {
+        Self {
+            under: control.under_alarm_enable.then(|| process_temp(control.under_limit)),
+            over: control.over_alarm_enable.then(|| process_temp(control.over_limit)),
+            sample_interval: if control.monitor_enable { control.sample_interval } else { 0 },
+            alarm_delay: control.alarm_delay,
+        }
+    }
+}
+
+/// Writes the alarm limits and monitoring settings to the selected tag and
+/// reads them back. The control words are read first and only the alarm
+/// fields change; the other configuration words are left alone.
+fn set_temperature_alarms<R: Gen2Transport>(
+    reader: &mut R,
+    alarms: TemperatureAlarms,
+) -> Result<TemperatureAlarms, Box<dyn Error>> {
+    let current = Em4325Config::read(reader)?.temp_sensor;
+    let words = alarms.to_control(&current)?.to_words();
+    let steps: Vec<ConfigStep> = [TEMP_SENSOR_CONTROL_WORD_1, TEMP_SENSOR_CONTROL_WORD_2, TEMP_SENSOR_CONTROL_WORD_3]
+        .into_iter()
+        .zip(words)
+        .map(|(address, value)| ConfigStep { name: word_name(address), address, value })
+        .collect();
+
+    let config = apply_config_transaction(reader, &steps)?;
+    Ok(TemperatureAlarms::from_control(&config.temp_sensor))
+}
+
+/// Alarm flags latched by the tag, with the number of out-of-limit samples
+/// counted in the TOTAL word.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct AlarmState {
+    under: bool,
+    over: bool,
+    out_of_limit: u16,
+}
+
+impl AlarmState {
+    /// Reads the alarm state from the selected tag. Reading the sensor data
+    /// takes a measurement, so it is checked against the limits as well.
+    fn read<R: Gen2Transport>(reader: &mut R) -> Gen2Result<Self> {
+        let data = read_sensor_data(reader)?;
+        let total = read_word(reader, TOTAL_WORD)?;
+        Ok(Self {
+            under: data.under_temp_alarm,
+            over: data.over_temp_alarm,
+            out_of_limit: TotalWord::from_word(total).count,
+        })
+    }
+
+    fn triggered(&self) -> bool {
+        self.under || self.over
+    }
+}
+
+impl std::fmt::Display for AlarmState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "under alarm {}, over alarm {}, {} out-of-limit samples",
+            on_off(self.under),
+            on_off(self.over),
+            self.out_of_limit
+        )
+    }
+}
+
+/// Clears the latched alarms and the out-of-limit count on the selected tag.
+fn clear_temperature_alarms<R: Gen2Transport>(reader: &mut R) -> Gen2Result<()> {
+    reader.write(MemoryBank::User, SENSOR_DATA_MSW, [0x00, 0x00], None)?;
+    reader.write(MemoryBank::User, TOTAL_WORD, [0x00, 0x00], None)
+}
+
+/// Memory areas the Gen2 Lock command covers, in payload order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LockTarget {
+    KillPassword,
+    AccessPassword,
+    Epc,
+    Tid,
+    /// All of user memory. The Lock command works per bank, so this is what
+    /// protects the EM4325 configuration words.
+    User,
+}
+
+/// What to do with a lock target. Password areas are locked against reads as
+/// well as writes; the memory banks only against writes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LockAction {
+    Unlock,
+    /// Unlocked, and it can never be locked.
+    PermaUnlock,
+    /// Only accessible with the access password.
+    Lock,
+    /// Never accessible again, with or without the password.
+    PermaLock,
+}
+
+/// Builds the 20-bit Lock payload: a mask bit pair per target saying which
+/// bits to change, then the action bit pair per target. Targets not listed
+/// keep their current lock state.
+fn lock_payload(actions: &[(LockTarget, LockAction)]) -> [u8; 3] {
+    let mut payload = 0u32;
+    for &(target, action) in actions {
+        let shift = 8 - 2 * target as u32;
+        let bits = match action {
+            LockAction::Unlock => 0b00,
+            LockAction::PermaUnlock => 0b01,
+            LockAction::Lock => 0b10,
+            LockAction::PermaLock => 0b11,
+        };
+        payload |= 0b11 << (shift + 10) | bits << shift;
+    }
+    let [_, a, b, c] = payload.to_be_bytes();
+    [a, b, c]
+}
+
+fn password_words(password: [u8; 4]) -> [[u8; 2]; 2] {
+    [[password[0], password[1]], [password[2], password[3]]]
+}
+
+/// Writes new kill and access passwords to the selected tag and reads them
+/// back with the new access password. `current` is the access password the
+/// tag has now, if the password bank is locked. The tag checks the password
+/// against what it holds at the time of each write, so once the first access
+/// password word has landed the second goes out under the half-updated
+/// password. If that last write fails, the error says what the password is.
+fn set_passwords<R: Gen2Transport>(
+    reader: &mut R,
+    kill: [u8; 4],
+    access: [u8; 4],
+    current: Option<[u8; 4]>,
+) -> Result<(), Box<dyn Error>> {
+    let [kill_hi, kill_lo] = password_words(kill);
+    let [access_hi, access_lo] = password_words(access);
+    let password = current.map(Password::from);
+    for (address, data) in [(0, kill_hi), (1, kill_lo), (2, access_hi)] {
+        reader.write(MemoryBank::Reserved, address, data, password)?;
+    }
+    let half = current.map(|[_, _, lo_hi, lo_lo]| [access_hi[0], access_hi[1], lo_hi, lo_lo]);
+    if let Err(err) = reader.write(MemoryBank::Reserved, 3, access_lo, half.map(Password::from)) {
+        let stored = half.map_or("unknown".to_string(), |half| to_hex(&half));
+        return Err(format!("access password half written ({err:?}), it is now {stored}").into());
+    }
+
+    let new = (access != [0; 4]).then(|| Password::from(access));
+    let stored = reader.read_alt(MemoryBank::Reserved, 0, 4, new)?;
+    if stored[..4] != kill || stored[4..8] != access {
+        return Err("passwords read back differ from what was written".into());
+    }
+    Ok(())
+}
+
+/// Sends a Lock command for `actions` to the selected tag.
+fn lock_tag<R: Gen2Transport>(
+    reader: &mut R,
+    actions: &[(LockTarget, LockAction)],
+    password: Option<Password>,
+) -> Gen2Result<()> {
+    reader.lock(lock_payload(actions), password)
+}
+
+/// Lock state of one area as far as it can be probed. Gen2 has no command
+/// for reading lock bits, so it is worked out from which accesses the tag
+/// refuses. A permanently unlocked area shows as `Open`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BankLock {
+    Open,
+    /// Refused without the access password. Without the password to try,
+    /// this also covers permalocked areas.
+    Locked,
+    Permalocked,
+}
+
+impl std::fmt::Display for BankLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            BankLock::Open => "open",
+            BankLock::Locked => "locked",
+            BankLock::Permalocked => "permalocked",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Memory banks are only probed on request, see [`LockState::probe_with_writes`];
+/// `None` means the bank wasn't probed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LockState {
+    kill_password: BankLock,
+    access_password: BankLock,
+    epc: Option<BankLock>,
+    tid: Option<BankLock>,
+    user: Option<BankLock>,
+}
+
+impl LockState {
+    /// Probes the password areas of the selected tag by reading them. Nothing
+    /// is written; memory bank locks only show up on a write, so they are
+    /// left unprobed.
+    ///
+    /// Each area is tried without a password first, so this refuses to run
+    /// through a reader that adds one. A given `password` is checked against
+    /// the tag first, so a wrong password isn't taken for a permalock.
+    fn probe<R: Gen2Transport>(reader: &mut R, password: Option<[u8; 4]>) -> Result<Self, Box<dyn Error>> {
+        Self::probe_areas(reader, password, false)
+    }
+
+    /// Like [`LockState::probe`], and also probes the memory banks by
+    /// writing to them: the first EPC word, the first TID word and
+    /// temperature sensor control word 1 are each written back with the value
+    /// they hold. Each write is a real write to the tag, so a write lost
+    /// halfway can leave a bad word behind.
+    fn probe_with_writes<R: Gen2Transport>(
+        reader: &mut R,
+        password: Option<[u8; 4]>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::probe_areas(reader, password, true)
+    }
+
+    fn probe_areas<R: Gen2Transport>(
+        reader: &mut R,
+        password: Option<[u8; 4]>,
+        writes: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        if reader.adds_password() {
+            return Err("lock state can't be probed through a reader that adds the password".into());
+        }
+        let password = password.map(Password::from);
+        if password.is_some() {
+            // a wrong password fails the Access and the tag doesn't answer;
+            // a permalocked access password still refuses the read
+            reader.read_alt(MemoryBank::Epc, 1, 1, None)?;
+            match reader.read_alt(MemoryBank::Reserved, 2, 2, password) {
+                Ok(_) | Err(StuhflError::Gen2MemLocked) => {}
+                Err(StuhflError::ChipNoResp) => return Err("wrong access password".into()),
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let mut attempt = |access: &mut dyn FnMut(&mut R, Option<Password>) -> Gen2Result<()>| {
+            match access(reader, None) {
+                Ok(()) => Ok(BankLock::Open),
+                Err(StuhflError::Gen2MemLocked) => match password {
+                    None => Ok(BankLock::Locked),
+                    Some(_) => match access(reader, password) {
+                        Ok(()) => Ok(BankLock::Locked),
+                        Err(StuhflError::Gen2MemLocked) => Ok(BankLock::Permalocked),
+                        Err(err) => Err(err),
+                    },
+                },
+                Err(err) => Err(err),
+            }
+        };
+        let read_back = |bank, address| {
+            move |reader: &mut R, password| -> Gen2Result<()> {
+                let word = reader.read_alt(bank, address, 1, None)?;
+                reader.write(bank, address, [word[0], word[1]], password)
+            }
+        };
+
+        let kill_password = attempt(&mut |r: &mut R, p| r.read_alt(MemoryBank::Reserved, 0, 2, p).map(|_| ()))?;
+        let access_password = attempt(&mut |r: &mut R, p| r.read_alt(MemoryBank::Reserved, 2, 2, p).map(|_| ()))?;
+        if !writes {
+            return Ok(Self {
+                kill_password,
+                access_password,
+                epc: None,
+                tid: None,
+                user: None,
+            });
+        }
+        Ok(Self {
+            kill_password,
+            access_password,
+            epc: Some(attempt(&mut read_back(MemoryBank::Epc, 2))?),
+            tid: Some(attempt(&mut read_back(MemoryBank::Tid, 0))?),
+            user: Some(attempt(&mut read_back(MemoryBank::User, TEMP_SENSOR_CONTROL_WORD_1))?),
+        })
+    }
+}
+
+impl std::fmt::Display for LockState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let bank = |lock: Option<BankLock>| lock.map_or("not probed".to_string(), |lock| lock.to_string());
+        write!(
+            f,
+            "kill password {}, access password {}, EPC {}, TID {}, user memory {}",
+            self.kill_password,
+            self.access_password,
+            bank(self.epc),
+            bank(self.tid),
+            bank(self.user)
+        )
+    }
+}
+
+/// Wraps a reader and sends `password` with every read, write and lock that
+/// doesn't bring its own, so the existing procedures work on locked tags.
+struct Authenticated<R: Gen2Transport> {
+    inner: R,
+    password: Password,
+}
+
+impl<R: Gen2Transport> Authenticated<R> {
+    fn new(inner: R, password: [u8; 4]) -> Self {
+        Self {
+            inner,
+            password: Password::from(password),
+        }
+    }
+
+    fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// The wrapped reader, for calls that must go out without the password.
+    fn inner_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+impl<R: Gen2Transport> Gen2Transport for Authenticated<R> {
+    fn inventory_once(&mut self) -> Gen2Result<(InventoryStatistics, Vec<InventoryTag>)> {
+        self.inner.inventory_once()
+    }
+
+    fn inventory(
+        &mut self,
+        rounds: u32,
+        callback: Box<dyn Fn(InventoryTag)>,
+    ) -> Gen2Result<InventoryStatistics> {
+        self.inner.inventory(rounds, callback)
+    }
+
+    fn select(&mut self, epc: &HexID) -> Gen2Result<()> {
+        self.inner.select(epc)
+    }
+
+    fn read_alt(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        num_words: u8,
+        password: Option<Password>,
+    ) -> Gen2Result<Vec<u8>> {
+        let password = password.or(Some(self.password));
+        self.inner.read_alt(bank, address, num_words, password)
+    }
+
+    fn write(
+        &mut self,
+        bank: MemoryBank,
+        address: u32,
+        data: [u8; 2],
+        password: Option<Password>,
+    ) -> Gen2Result<()> {
+        let password = password.or(Some(self.password));
+        self.inner.write(bank, address, data, password)
+    }
+
+    fn lock(&mut self, payload: [u8; 3], password: Option<Password>) -> Gen2Result<()> {
+        let password = password.or(Some(self.password));
+        self.inner.lock(payload, password)
+    }
+
+    fn tune(&mut self, algorithm: TuningAlgorithm) -> Gen2Result<()> {
+        self.inner.tune(algorithm)
+    }
+
+    fn reflected_power(&mut self, frequency: u32) -> Gen2Result<(i8, i8)> {
+        self.inner.reflected_power(frequency)
+    }
+
+    fn adds_password(&self) -> bool {
+        true
+    }
+}
+
+/// How an EM4325 I/O pin is used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PinMode {
+    /// Plain input; the level can be read from the I/O state word.
+    Input,
+    /// Output driven at the given level.
+    Output(bool),
+    /// Input that latches an event on a rising edge and sets the aux event
+    /// flag in the sensor data, e.g. a door-open or tamper switch.
+    Aux,
+}
+
+impl IoControl {
+    const PINS: u8 = 4;
+
+    fn pin_mode(&self, pin: u8) -> PinMode {
+        if self.pin_output_enable & 1 << pin != 0 {
+            PinMode::Output(self.pin_output_level & 1 << pin != 0)
+        } else if self.pin_event_enable & 1 << pin != 0 {
+            PinMode::Aux
+        } else {
+            PinMode::Input
+        }
+    }
+
+    fn set_pin_mode(&mut self, pin: u8, mode: PinMode) {
+        let mask = 1 << pin;
+        self.pin_output_enable &= !mask;
+        self.pin_output_level &= !mask;
+        self.pin_event_enable &= !mask;
+        match mode {
+            PinMode::Input => {}
+            PinMode::Output(level) => {
+                self.pin_output_enable |= mask;
+                if level {
+                    self.pin_output_level |= mask;
+                }
+            }
+            PinMode::Aux => self.pin_event_enable |= mask,
+        }
+    }
+}
+
+/// Configures I/O pin `pin` (0-3) on the selected tag and returns the I/O
+/// control word as read back. The pins are only free for this with the
+/// discrete I/O interface; the SPI interfaces use them for the bus.
+fn set_pin_mode<R: Gen2Transport>(reader: &mut R, pin: u8, mode: PinMode) -> Result<IoControl, Box<dyn Error>> {
+    if pin >= IoControl::PINS {
+        return Err(format!("there is no pin P{pin}").into());
+    }
+    let mut io = IoControl::from_word(read_word(reader, IO_CONTROL_WORD)?);
+    if io.interface != IoInterface::DiscreteIo {
+        return Err(format!("pins are in use by the {:?} interface", io.interface).into());
+    }
+
+    io.set_pin_mode(pin, mode);
+    let step = ConfigStep {
+        name: word_name(IO_CONTROL_WORD),
+        address: IO_CONTROL_WORD,
+        value: io.to_word(),
+    };
+    Ok(apply_config_transaction(reader, &[step])?.io)
+}
+
+/// Drives output pin `pin`. The pin has to be configured as an output already.
+fn drive_pin<R: Gen2Transport>(reader: &mut R, pin: u8, level: bool) -> Result<(), Box<dyn Error>> {
+    let io = IoControl::from_word(read_word(reader, IO_CONTROL_WORD)?);
+    if pin >= IoControl::PINS || !matches!(io.pin_mode(pin), PinMode::Output(_)) {
+        return Err(format!("P{pin} is not configured as an output").into());
+    }
+    set_pin_mode(reader, pin, PinMode::Output(level))?;
+    Ok(())
+}
+
+/// I/O state word 0x10C.
+///
+/// The bit layout below (levels in bits 7-4, latched events in bits 3-0, one
+/// bit per pin) is this file's working assumption, not taken from a cited
+/// datasheet table. It has only been checked against `SimEm4325`, which is
+/// built on the same assumption, so confirm it on a real tag before relying
+/// on it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct IoState {
+    /// Bits 7-4: level currently seen on each pin (bit n is Pn). Read-only.
+    inputs: u8,
+    /// Bits 3-0: events latched by aux pins since they were last cleared.
+    events: u8,
+}
+
+impl IoState {
+    fn from_word(word: u16) -> Self {
+        Self {
+            inputs: (word >> 4 & 0xF) as u8,
+            events: (word & 0xF) as u8,
+        }
+    }
+
+    fn read<R: Gen2Transport>(reader: &mut R) -> Gen2Result<Self> {
+        Ok(Self::from_word(read_word(reader, IO_STATE_WORD)?))
+    }
+
+    fn input(&self, pin: u8) -> bool {
+        self.inputs & 1 << pin != 0
+    }
+
+    fn event(&self, pin: u8) -> bool {
+        self.events & 1 << pin != 0
+    }
+}
+
+impl std::fmt::Display for IoState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "inputs {:04b}, latched events {:04b}", self.inputs, self.events)
+    }
+}
+
+/// Clears the latched pin events and the aux event flag in the sensor data,
+/// writing the pin level bits back as read.
+fn clear_pin_events<R: Gen2Transport>(reader: &mut R) -> Gen2Result<()> {
+    let state = read_word(reader, IO_STATE_WORD)?;
+    reader.write(MemoryBank::User, IO_STATE_WORD, (state & 0x00F0).to_be_bytes(), None)?;
+    let msw = read_word(reader, SENSOR_DATA_MSW)?;
+    reader.write(MemoryBank::User, SENSOR_DATA_MSW, (msw & 0x7FFF).to_be_bytes(), None)
+}
+
+/// Switches the simple-sensor reply of the selected tag on or off and returns
+/// whether it was on before. When on, the tag answers every inventory round
+/// with its sensor data words after the EPC, so no select and read is needed
+/// per tag.
+fn set_simple_sensor_mode<R: Gen2Transport>(reader: &mut R, enabled: bool) -> Gen2Result<bool> {
+    let pc = reader.read_alt(MemoryBank::Epc, 1, 1, None)?;
+    let pc = u16::from_be_bytes([pc[0], pc[1]]);
+    let was_enabled = bit(pc, PC_XI_BIT);
+    let pc = pc & !(1 << PC_XI_BIT) | set_bit(enabled, PC_XI_BIT);
+    reader.write(MemoryBank::Epc, 1, pc.to_be_bytes(), None)?;
+    Ok(was_enabled)
+}
+
+/// One tag's answer to a simple-sensor inventory round.
+#[derive(Clone, Debug, PartialEq)]
+struct SensorReply {
+    epc: HexID,
+    tid: HexID,
+    /// `None` if the tag answered without sensor data, i.e. it isn't in
+    /// simple-sensor mode.
+    data: Option<SensorData>,
+}
+
+impl SensorReply {
+    /// Splits the sensor data off an inventory reply. `epc_len` is the length
+    /// of the tags' own EPC in bytes; a reply 4 bytes longer carries sensor data.
+    fn parse(tag: &InventoryTag, epc_len: usize) -> Self {
+        let bytes = hex_bytes(&tag.epc);
+        let (epc, data) = if bytes.len() == epc_len + 4 {
+            let (epc, data) = bytes.split_at(epc_len);
+            (HexID::from(epc.to_vec()), Some(SensorData::from_bytes(data)))
+        } else {
+            (tag.epc.clone(), None)
+        };
+        Self {
+            epc,
+            tid: tag.tid.clone(),
+            data,
+        }
+    }
+}
+
+/// Runs one inventory round and returns every tag's own sensor reading.
+/// Tags that answer more than once are reported once, with their last reply.
+fn inventory_sensor_data<R: Gen2Transport>(reader: &mut R, epc_len: usize) -> Gen2Result<Vec<SensorReply>> {
+    let (_, tags) = reader.inventory_once()?;
+    let mut replies: Vec<SensorReply> = Vec::new();
+    for reply in tags.iter().map(|tag| SensorReply::parse(tag, epc_len)) {
+        match replies.iter_mut().find(|r| r.epc == reply.epc) {
+            Some(existing) => *existing = reply,
+            None => replies.push(reply),
+        }
+    }
+    Ok(replies)
+}
+
+/// Fails if one of `epcs` answered without sensor data, which means setting
+/// its XI bit didn't switch on the simple-sensor reply (see [`PC_XI_BIT`]).
+fn check_sensor_replies(replies: &[SensorReply], epcs: &[HexID]) -> Result<(), String> {
+    for reply in replies.iter().filter(|reply| reply.data.is_none()) {
+        let bytes = hex_bytes(&reply.epc);
+        if epcs.iter().any(|epc| bytes.starts_with(&hex_bytes(epc))) {
+            return Err(format!(
+                "{} answered without sensor data in simple-sensor mode, its reply isn't EPC + 4 bytes",
+                reply.epc
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Puts `epcs` into simple-sensor mode for one inventory round, then switches
+/// the mode back off on every tag that didn't already have it on, whether or
+/// not the round succeeded. A tag that answers without its sensor data fails
+/// the round. The first error is returned.
+fn simple_sensor_round<R: Gen2Transport>(
+    reader: &mut R,
+    epcs: &[HexID],
+    epc_len: usize,
+) -> Result<Vec<SensorReply>, Box<dyn Error>> {
+    let mut switched_on = Vec::new();
+    let mut enabled = Ok(());
+    for epc in epcs {
+        match reader.select(epc).and_then(|()| set_simple_sensor_mode(reader, true)) {
+            Ok(true) => {}
+            Ok(false) => switched_on.push(epc),
+            Err(err) => {
+                enabled = Err(err);
+                break;
+            }
+        }
+    }
+    let replies = match enabled.and_then(|()| inventory_sensor_data(reader, epc_len)) {
+        Ok(replies) => check_sensor_replies(&replies, epcs).map(|()| replies).map_err(Box::<dyn Error>::from),
+        Err(err) => Err(err.into()),
+    };
+
+    let mut restored = Ok(());
+    for epc in switched_on {
+        if let Err(err) = reader.select(epc).and_then(|()| set_simple_sensor_mode(reader, false)) {
+            if restored.is_ok() {
+                restored = Err(err);
+            }
+        }
+    }
+    let replies = replies?;
+    restored?;
+    Ok(replies)
+}
+
+/// Selects each tag in turn and reads its own temperature. A tag that
+/// doesn't answer, or answers with a stale measurement, gets a failed row.
+fn read_tag_temperatures<R: Gen2Transport>(
+    reader: &mut R,
+    clock: &dyn Clock,
+    tags: &[InventoryTag],
+) -> Vec<TemperatureData> {
+    tags.iter()
+        .map(|tag| {
+            let temperature = match reader.select(&tag.epc).and_then(|()| read_sensor_data(reader)) {
+                Ok(data) if data.valid => Ok(data.temperature),
+                Ok(_) => Err("stale".to_string()),
+                Err(err) => Err(format!("{err:?}")),
+            };
+            TemperatureData {
+                epc: tag.epc.clone(),
+                tid: tag.tid.clone(),
+                timestamp: clock.now().with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+                temperature,
+            }
+        })
+        .collect()
+}
+
+/// Appends rows to the day's temperature log, named after `clock`'s date.
+/// The rows carry a status column the older `temperature_log <date>.csv`
+/// files don't have, so they go to a file of their own.
+fn append_temperature_log(clock: &dyn Clock, dir: &std::path::Path, rows: &[TemperatureData]) -> io::Result<()> {
+    let now = clock.now().with_timezone(&Local);
+    let csv_filename = dir.join(format!("temperature_status_log {}.csv", now.format("%Y-%m-%d")));
+
+    let mut csv_file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&csv_filename)?;
+
+    if csv_file.metadata()?.len() == 0 {
+        writeln!(&mut csv_file, "EPC, TID, Timestamp, Temperature (Celsius), Status")?;
+    }
+
+    for row in rows {
+        writeln!(&mut csv_file, "{}", row.csv_row())?;
+    }
+    Ok(())
+}
+
+/// Charge time above which a tag is flagged: the capacitor or battery is no
+/// longer holding up.
+const SLOW_CHARGE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Battery and BAP health of one semi-passive tag.
+#[derive(Clone, Debug, PartialEq)]
+struct BatteryHealth {
+    epc: HexID,
+    battery_detected: bool,
+    /// Low-battery flag from battery management word 2 or the sensor data.
+    battery_low: bool,
+    /// How long the field took to charge the storage capacitor enough to
+    /// answer a write after a discharge, or why it couldn't be measured.
+    charge_time: Result<std::time::Duration, String>,
+    /// Writes it took to get an answer while charging.
+    charge_attempts: u32,
+}
+
+impl BatteryHealth {
+    /// Why the tag needs attention, or `None` if it looks healthy.
+    fn problem(&self) -> Option<String> {
+        if !self.battery_detected {
+            Some("no battery detected".to_string())
+        } else if self.battery_low {
+            Some("battery low, replace it".to_string())
+        } else {
+            match &self.charge_time {
+                Err(reason) => Some(format!("capacitor didn't charge: {reason}")),
+                Ok(time) if *time > SLOW_CHARGE => Some(format!("slow to charge ({time:?})")),
+                Ok(_) => None,
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for BatteryHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: battery {}{}, ",
+            self.epc,
+            if self.battery_detected { "detected" } else { "not detected" },
+            if self.battery_low { " (low)" } else { "" }
+        )?;
+        match &self.charge_time {
+            Ok(time) => write!(f, "charged in {time:?} ({} writes)", self.charge_attempts)?,
+            Err(reason) => write!(f, "charge failed ({reason})")?,
+        }
+        match self.problem() {
+            Some(problem) => write!(f, " - {problem}"),
+            None => write!(f, " - ok"),
+        }
+    }
+}
+
+/// Checks the battery of tag `epc` and times how long the field takes to
+/// charge its storage capacitor: the discharge and charge phases of a
+/// pseudo-BAP measurement, with BAP left off afterwards.
+fn diagnose_battery<R: Gen2Transport>(
+    reader: &mut R,
+    clock: &dyn Clock,
+    epc: &HexID,
+    timing: PseudoBapTiming,
+) -> Gen2Result<BatteryHealth> {
+    reader.select(epc)?;
+    let battery = BatteryManagement2::from_word(read_word(reader, BATTERY_MANAGEMENT_WORD_2)?);
+    let data = read_sensor_data(reader)?;
+
+    let mut measurement = PseudoBapMeasurement::new(timing);
+    let mut charge_time = Err("not measured".to_string());
+    while let Some(PseudoBapPhase::Discharge | PseudoBapPhase::Charge) = measurement.phase() {
+        let result = measurement.step(reader, clock, &mut |event| {
+            // the charge phase's own time includes the inventory after it
+            if let PseudoBapEvent::Charged { after } = event {
+                charge_time = Ok(after);
+            }
+        });
+        if let Err(err) = result {
+            // BAP may have been left on; try to switch it off
+            reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None).ok();
+            charge_time = Err(err.to_string());
+            break;
+        }
+    }
+
+    Ok(BatteryHealth {
+        epc: epc.clone(),
+        battery_detected: battery.battery_detected,
+        battery_low: battery.battery_low || data.battery_low,
+        charge_time,
+        charge_attempts: measurement.charge_attempts,
+    })
+}
+
+/// Health report for every tag in `tags`. Tags that can't be read at all
+/// are reported with the error.
+fn battery_health_report<R: Gen2Transport>(
+    reader: &mut R,
+    clock: &dyn Clock,
+    tags: &[InventoryTag],
+    timing: PseudoBapTiming,
+) -> Vec<(HexID, Gen2Result<BatteryHealth>)> {
+    tags.iter()
+        .map(|tag| (tag.epc.clone(), diagnose_battery(reader, clock, &tag.epc, timing)))
+        .collect()
+}
+
+/// ADXL363 measurement range (FILTER_CTL bits 7-6).
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AdxlRange {
+    G2,
+    G4,
+    G8,
+}
+
+impl AdxlRange {
+    /// mg per LSB of the 12-bit output.
+    fn scale_mg(self) -> f32 {
+        match self {
+            AdxlRange::G2 => 1.0,
+            AdxlRange::G4 => 2.0,
+            AdxlRange::G8 => 4.0,
+        }
+    }
+}
+
+/// ADXL363 output data rate (FILTER_CTL bits 2-0).
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AdxlOdr {
+    Hz12_5,
+    Hz25,
+    Hz50,
+    Hz100,
+    Hz200,
+    Hz400,
+}
+
+impl AdxlOdr {
+    fn hz(self) -> f32 {
+        12.5 * (1 << self as u8) as f32
+    }
+}
+
+/// Anti-aliasing filter bandwidth (FILTER_CTL bit 4, HALF_BW).
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AdxlBandwidth {
+    /// ODR/2.
+    HalfOdr,
+    /// ODR/4, for more margin against aliasing.
+    QuarterOdr,
+}
+
+/// Power versus noise trade-off (POWER_CTL bits 5-4).
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AdxlNoise {
+    Normal,
+    Low,
+    UltraLow,
+}
+
+/// FIFO mode (FIFO_CONTROL bits 1-0).
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FifoMode {
+    Disabled,
+    /// Fills up and then stops, keeping the oldest samples.
+    OldestSaved,
+    /// Keeps the newest samples, dropping the oldest once full.
+    Stream,
+    /// Keeps samples from around an activity trigger.
+    Triggered,
+}
+
+/// ADXL363 settings for the purple tags, written to FILTER_CTL, FIFO_CONTROL,
+/// FIFO_SAMPLES and POWER_CTL. Built with `AdxlConfig::builder()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AdxlConfig {
+    range: AdxlRange,
+    odr: AdxlOdr,
+    bandwidth: AdxlBandwidth,
+    noise: AdxlNoise,
+    fifo_mode: FifoMode,
+    /// FIFO watermark in words (FIFO_SAMPLES plus FIFO_CONTROL bit 3).
+    watermark: u16,
+    /// Store a temperature word after every X/Y/Z set (FIFO_CONTROL bit 2).
+    fifo_temperature: bool,
+}
+
+struct AdxlConfigBuilder {
+    config: AdxlConfig,
+}
+
+impl AdxlConfig {
+    /// Starts from the power-on settings: ±2 g, 100 Hz, ODR/4 bandwidth,
+    /// normal noise, FIFO off with a watermark of 128 words.
+    fn builder() -> AdxlConfigBuilder {
+        AdxlConfigBuilder {
+            config: AdxlConfig {
+                range: AdxlRange::G2,
+                odr: AdxlOdr::Hz100,
+                bandwidth: AdxlBandwidth::QuarterOdr,
+                noise: AdxlNoise::Normal,
+                fifo_mode: FifoMode::Disabled,
+                watermark: 0x80,
+                fifo_temperature: false,
+            },
+        }
+    }
+
+    /// FIFO words per sample: X, Y, Z and the optional temperature.
+    fn words_per_sample(&self) -> u16 {
+        if self.fifo_temperature {
+            4
+        } else {
+            3
+        }
+    }
+
+    fn filter_ctl(&self) -> u8 {
+        (self.range as u8) << 6 | ((self.bandwidth == AdxlBandwidth::QuarterOdr) as u8) << 4 | self.odr as u8
+    }
+
+    fn fifo_control(&self) -> u8 {
+        ((self.watermark >> 8) as u8 & 1) << 3 | (self.fifo_temperature as u8) << 2 | self.fifo_mode as u8
+    }
+
+    fn fifo_samples(&self) -> u8 {
+        self.watermark as u8
+    }
+
+    fn power_ctl(&self, measuring: bool) -> u8 {
+        (self.noise as u8) << 4 | if measuring { 0b10 } else { 0b00 }
+    }
+
+    /// Writes the configuration with measurement off.
+    fn write<A: AdxlAccess>(&self, adxl: &mut A) -> Gen2Result<()> {
+        adxl.write_register(AdxlRegister::PowerCtl, &[self.power_ctl(false)])?;
+        adxl.write_register(AdxlRegister::FilterCtl, &[self.filter_ctl()])?;
+        adxl.write_register(AdxlRegister::FifoSamples, &[self.fifo_samples()])?;
+        adxl.write_register(AdxlRegister::FifoControl, &[self.fifo_control()])
+    }
+
+    /// Turns measurement on or off, keeping the configured noise mode.
+    fn set_measuring<A: AdxlAccess>(&self, adxl: &mut A, measuring: bool) -> Gen2Result<()> {
+        adxl.write_register(AdxlRegister::PowerCtl, &[self.power_ctl(measuring)])
+    }
+}
+
+impl AdxlConfigBuilder {
+    fn range(mut self, range: AdxlRange) -> Self {
+        self.config.range = range;
+        self
+    }
+
+    fn odr(mut self, odr: AdxlOdr) -> Self {
+        self.config.odr = odr;
+        self
+    }
+
+    fn bandwidth(mut self, bandwidth: AdxlBandwidth) -> Self {
+        self.config.bandwidth = bandwidth;
+        self
+    }
+
+    fn noise(mut self, noise: AdxlNoise) -> Self {
+        self.config.noise = noise;
+        self
+    }
+
+    fn fifo_mode(mut self, fifo_mode: FifoMode) -> Self {
+        self.config.fifo_mode = fifo_mode;
+        self
+    }
+
+    fn watermark(mut self, watermark: u16) -> Self {
+        self.config.watermark = watermark;
+        self
+    }
+
+    fn fifo_temperature(mut self, fifo_temperature: bool) -> Self {
+        self.config.fifo_temperature = fifo_temperature;
+        self
+    }
+
+    fn build(self) -> Result<AdxlConfig, String> {
+        let config = self.config;
+        if config.fifo_mode == FifoMode::Disabled {
+            if config.fifo_temperature {
+                return Err("temperature in FIFO needs the FIFO enabled".to_string());
+            }
+            return Ok(config);
+        }
+        if !(1..=511).contains(&config.watermark) {
+            return Err(format!("FIFO watermark {} is outside 1-511 words", config.watermark));
+        }
+        if config.watermark % config.words_per_sample() != 0 {
+            return Err(format!(
+                "FIFO watermark {} splits a sample of {} words",
+                config.watermark,
+                config.words_per_sample()
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// ADXL settings for the vibration tests: fast enough for motor vibration,
+/// streaming into the FIFO.
+fn vibration_config() -> AdxlConfig {
+    AdxlConfig::builder()
+        .range(AdxlRange::G4)
+        .odr(AdxlOdr::Hz400)
+        .bandwidth(AdxlBandwidth::HalfOdr)
+        .fifo_mode(FifoMode::Stream)
+        .watermark(480)
+        .build()
+        .expect("vibration ADXL config is valid")
+}
+
+/// SPI mode: clock polarity and phase.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SpiMode {
+    /// Clock idles low, data sampled on the rising edge.
+    Mode0,
+    /// Clock idles low, data sampled on the falling edge.
+    Mode1,
+    /// Clock idles high, data sampled on the falling edge.
+    Mode2,
+    /// Clock idles high, data sampled on the rising edge.
+    Mode3,
+}
+
+/// Bus settings for one SPI transfer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SpiConfig {
+    clock_khz: u16,
+    mode: SpiMode,
+}
+
+/// An SPI master that reaches a peripheral on a tag, such as the EM4325
+/// driving the sensor on its I/O pins. A transfer holds chip select for its
+/// whole length: it clocks out `tx`, then clocks in `rx_len` bytes, so one
+/// transfer covers a plain write, a write-then-read or a multi-byte burst.
+///
+/// `Gen2Reader` doesn't implement it: the EM4325 command encoding is in
+/// `tag_sensors::adxl363`, which is how the ADXL363 on a tag is still reached.
+trait SpiMaster {
+    fn spi_transfer(&mut self, config: SpiConfig, tx: &[u8], rx_len: usize) -> Gen2Result<Vec<u8>>;
+
+    fn spi_write(&mut self, config: SpiConfig, tx: &[u8]) -> Gen2Result<()> {
+        self.spi_transfer(config, tx, 0).map(|_| ())
+    }
+}
+
+/// ADXL363 SPI commands.
+const ADXL_WRITE_REGISTER: u8 = 0x0A;
+const ADXL_READ_REGISTER: u8 = 0x0B;
+const ADXL_READ_FIFO: u8 = 0x0D;
+/// ADXL363 registers that only the SPI command layer uses, and the code that
+/// SOFT_RESET takes.
+const ADXL_FIFO_ENTRIES_L: u8 = 0x0C;
+const ADXL_SOFT_RESET: u8 = 0x1F;
+const ADXL_SOFT_RESET_CODE: u8 = 0x52;
+/// The ADXL363 wants mode 0 and takes up to 8 MHz
+/// (`SimAdxl363::MAX_SCLK_KHZ`), well above this.
+const ADXL_SPI: SpiConfig = SpiConfig {
+    clock_khz: 100,
+    mode: SpiMode::Mode0,
+};
+
+/// Reads `len` ADXL363 registers from `address` on in one burst.
+fn adxl_spi_read<S: SpiMaster>(spi: &mut S, address: u8, len: u16) -> Gen2Result<Vec<u8>> {
+    spi.spi_transfer(ADXL_SPI, &[ADXL_READ_REGISTER, address], len as usize)
+}
+
+/// Writes `data` to consecutive ADXL363 registers from `address` on.
+fn adxl_spi_write<S: SpiMaster>(spi: &mut S, address: u8, data: &[u8]) -> Gen2Result<()> {
+    let mut tx = vec![ADXL_WRITE_REGISTER, address];
+    tx.extend_from_slice(data);
+    spi.spi_write(ADXL_SPI, &tx)
+}
+
+/// Pops `num_bytes` of ADXL363 FIFO data, two little-endian bytes per word.
+fn adxl_spi_read_fifo<S: SpiMaster>(spi: &mut S, num_bytes: u16) -> Gen2Result<Vec<u8>> {
+    spi.spi_transfer(ADXL_SPI, &[ADXL_READ_FIFO], num_bytes as usize)
+}
+
+/// ADXL363 registers the procedures in this file touch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AdxlRegister {
+    DevIdAd = 0x00,
+    XDataL = 0x0E,
+    TimeInactL = 0x25,
+    FifoControl = 0x28,
+    FifoSamples = 0x29,
+    FilterCtl = 0x2C,
+    PowerCtl = 0x2D,
+    SelfTest = 0x2E,
+}
+
+impl AdxlRegister {
+    /// The same register as `tag_sensors::adxl363` names it, for the
+    /// registers this file already reaches through it. `None` for the ones in
+    /// 0x26-0x2D, which go through a burst from TIME_INACT_L instead.
+    fn tag_register(self) -> Option<adxl::Register> {
+        match self {
+            AdxlRegister::DevIdAd => Some(adxl::Register::DevIdAd),
+            AdxlRegister::XDataL => Some(adxl::Register::XDataL),
+            AdxlRegister::TimeInactL => Some(adxl::Register::TimeInactL),
+            AdxlRegister::SelfTest => Some(adxl::Register::SelfTest),
+            AdxlRegister::FifoControl
+            | AdxlRegister::FifoSamples
+            | AdxlRegister::FilterCtl
+            | AdxlRegister::PowerCtl => None,
+        }
+    }
+
+    /// Registers between TIME_INACT_L and this one.
+    fn time_inact_offset(self) -> u16 {
+        self as u16 - AdxlRegister::TimeInactL as u16
+    }
+}
+
+/// Reads `len` registers from `register` on by reading a burst from
+/// TIME_INACT_L, relying on the ADXL363 moving to the next register on each
+/// byte of a multibyte access. `register` must not be below TIME_INACT_L.
+fn read_from_time_inact<A: AdxlAccess>(adxl: &mut A, register: AdxlRegister, len: u16) -> Gen2Result<Vec<u8>> {
+    let offset = register.time_inact_offset();
+    let bytes = adxl.read_register(AdxlRegister::TimeInactL, offset + len)?;
+    Ok(bytes[offset as usize..].to_vec())
+}
+
+/// Writes `data` from `register` on as one burst from TIME_INACT_L, with the
+/// registers in front of it written back as read. `register` must not be
+/// below TIME_INACT_L.
+fn write_from_time_inact<A: AdxlAccess>(adxl: &mut A, register: AdxlRegister, data: &[u8]) -> Gen2Result<()> {
+    let offset = register.time_inact_offset();
+    let mut bytes = adxl.read_register(AdxlRegister::TimeInactL, offset)?;
+    bytes.extend_from_slice(data);
+    adxl.write_register(AdxlRegister::TimeInactL, &bytes)
+}
+
+/// DEVID_AD, DEVID_MST and PARTID of an ADXL363.
+const ADXL_ID: [u8; 3] = [0xAD, 0x1D, 0xF3];
+
+/// The ADXL363 accesses the procedures in this file need, so they run
+/// against the simulated part as well as a tag.
+trait AdxlAccess {
+    /// Reads `len` registers from `register` on in one burst.
+    fn read_register(&mut self, register: AdxlRegister, len: u16) -> Gen2Result<Vec<u8>>;
+
+    /// Writes `data` to consecutive registers from `register` on.
+    fn write_register(&mut self, register: AdxlRegister, data: &[u8]) -> Gen2Result<()>;
+
+    /// Words waiting in the FIFO.
+    fn fifo_entries(&mut self) -> Gen2Result<u16>;
+
+    /// Pops `num_bytes` of FIFO data, two little-endian bytes per word.
+    fn read_fifo(&mut self, num_bytes: u16) -> Gen2Result<Vec<u8>>;
+
+    /// Puts the part in the purple tags' default configuration with
+    /// measurement off.
+    fn setup(&mut self) -> Gen2Result<()>;
+
+    /// Whether the ID registers read back as an ADXL363.
+    fn connected(&mut self) -> Gen2Result<bool> {
+        Ok(self.read_register(AdxlRegister::DevIdAd, 3)? == ADXL_ID)
+    }
+
+    /// Turns measurement on or off, keeping the rest of POWER_CTL.
+    fn set_measuring(&mut self, on: bool) -> Gen2Result<()> {
+        let power_ctl = self.read_register(AdxlRegister::PowerCtl, 1)?[0] & !0b11;
+        self.write_register(AdxlRegister::PowerCtl, &[power_ctl | if on { 0b10 } else { 0b00 }])
+    }
+
+    /// Reads XDATA to ZDATA as signed LSB.
+    fn read_xyz(&mut self) -> Gen2Result<[i16; 3]> {
+        Ok(xyz_from_bytes(&self.read_register(AdxlRegister::XDataL, 6)?))
+    }
+
+    /// Sets or clears the ST bit in SELF_TEST.
+    fn set_self_test(&mut self, on: bool) -> Gen2Result<()> {
+        self.write_register(AdxlRegister::SelfTest, &[on as u8])
+    }
+}
+
+fn xyz_from_bytes(bytes: &[u8]) -> [i16; 3] {
+    [0, 1, 2].map(|i| i16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]))
+}
+
+/// Reaches the ADXL363 on the selected tag through `tag_sensors::adxl363`.
+/// Only the registers this file already used through it are named directly;
+/// FIFO_CONTROL to POWER_CTL are reached by a burst from TIME_INACT_L, see
+/// [`AdxlRegister::tag_register`].
+impl AdxlAccess for Gen2Reader {
+    fn read_register(&mut self, register: AdxlRegister, len: u16) -> Gen2Result<Vec<u8>> {
+        match register.tag_register() {
+            Some(tag_register) => adxl::read_register(self, tag_register, len),
+            None => read_from_time_inact(self, register, len),
+        }
+    }
+
+    fn write_register(&mut self, register: AdxlRegister, data: &[u8]) -> Gen2Result<()> {
+        match register.tag_register() {
+            Some(tag_register) => {
+                adxl::write_register(self, tag_register, data)?;
+                Ok(())
+            }
+            None => write_from_time_inact(self, register, data),
+        }
+    }
+
+    fn fifo_entries(&mut self) -> Gen2Result<u16> {
+        adxl::get_num_fifo_entries(self)
+    }
+
+    fn read_fifo(&mut self, num_bytes: u16) -> Gen2Result<Vec<u8>> {
+        adxl::read_fifo(self, num_bytes)
+    }
+
+    fn setup(&mut self) -> Gen2Result<()> {
+        adxl::setup(self)
+    }
+
+    fn connected(&mut self) -> Gen2Result<bool> {
+        adxl::test_adxl_connection(self)
+    }
+
+    fn set_measuring(&mut self, on: bool) -> Gen2Result<()> {
+        if on {
+            adxl::turn_on(self)?;
+        } else {
+            adxl::turn_off(self)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reaches the ADXL363 with its own SPI commands. `setup` soft-resets the part
+/// and leaves it at ±2 g and 12.5 Hz with the FIFO streaming, which is what the
+/// sensor flows expect `adxl363::setup` to leave behind.
+impl<S: SpiMaster> AdxlAccess for S {
+    fn read_register(&mut self, register: AdxlRegister, len: u16) -> Gen2Result<Vec<u8>> {
+        adxl_spi_read(self, register as u8, len)
+    }
+
+    fn write_register(&mut self, register: AdxlRegister, data: &[u8]) -> Gen2Result<()> {
+        adxl_spi_write(self, register as u8, data)
+    }
+
+    fn fifo_entries(&mut self) -> Gen2Result<u16> {
+        let entries = adxl_spi_read(self, ADXL_FIFO_ENTRIES_L, 2)?;
+        Ok(u16::from_le_bytes([entries[0], entries[1]]) & 0x3FF)
+    }
+
+    fn read_fifo(&mut self, num_bytes: u16) -> Gen2Result<Vec<u8>> {
+        adxl_spi_read_fifo(self, num_bytes)
+    }
+
+    fn setup(&mut self) -> Gen2Result<()> {
+        adxl_spi_write(self, ADXL_SOFT_RESET, &[ADXL_SOFT_RESET_CODE])?;
+        adxl_spi_write(self, AdxlRegister::FilterCtl as u8, &[0x10])?;
+        adxl_spi_write(self, AdxlRegister::FifoControl as u8, &[0x02])
+    }
+}
+
+/// Datasheet self-test output change limits in mg, X/Y/Z.
+const SELF_TEST_LIMITS_MG: [std::ops::RangeInclusive<f32>; 3] = [450.0..=710.0, -710.0..=-450.0, 350.0..=590.0];
+/// Readings averaged with the ST bit clear and again with it set.
+const SELF_TEST_SAMPLES: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+struct AxisSelfTest {
+    /// Output change between self-test off and on, in mg.
+    change_mg: f32,
+    passed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SelfTestResult {
+    axes: [AxisSelfTest; 3],
+}
+
+impl SelfTestResult {
+    fn passed(&self) -> bool {
+        self.axes.iter().all(|axis| axis.passed)
+    }
+}
+
+impl std::fmt::Display for SelfTestResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, (name, axis)) in ["X", "Y", "Z"].iter().zip(&self.axes).enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            let verdict = if axis.passed { "pass" } else { "FAIL" };
+            write!(f, "{name} {:+.0} mg {verdict}", axis.change_mg)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the ADXL363 self-test on a part that is measuring with `config`:
+/// averages readings with the ST bit clear and then set, converts the change
+/// to mg for the configured range and checks each axis against the
+/// datasheet limits. The ST bit is cleared again even if a read fails.
+fn self_test<A: AdxlAccess>(adxl: &mut A, clock: &dyn Clock, config: &AdxlConfig) -> Gen2Result<SelfTestResult> {
+    let period = std::time::Duration::from_secs_f32(1.0 / config.odr.hz());
+    let average = |adxl: &mut A| -> Gen2Result<[f32; 3]> {
+        let mut sum = [0.0; 3];
+        for _ in 0..SELF_TEST_SAMPLES {
+            clock.sleep(period);
+            for (sum, value) in sum.iter_mut().zip(adxl.read_xyz()?) {
+                *sum += value as f32;
+            }
+        }
+        Ok(sum.map(|sum| sum / SELF_TEST_SAMPLES as f32))
+    };
+
+    let before = average(adxl)?;
+    adxl.set_self_test(true)?;
+    // wait for the output to settle to its new value
+    clock.sleep(period * 4);
+    let after = average(adxl);
+    adxl.set_self_test(false)?;
+    let after = after?;
+
+    let axes = [0, 1, 2].map(|i| {
+        let change_mg = (after[i] - before[i]) * config.range.scale_mg();
+        AxisSelfTest {
+            change_mg,
+            passed: SELF_TEST_LIMITS_MG[i].contains(&change_mg),
+        }
+    });
+    Ok(SelfTestResult { axes })
+}
+
+/// Commissioning check for a purple tag: ADXL connection, the default
+/// configuration and a self-test, leaving measurement off afterwards.
+fn commission_adxl<A: AdxlAccess>(adxl: &mut A, clock: &dyn Clock) -> Result<SelfTestResult, Box<dyn Error>> {
+    if !adxl.connected()? {
+        return Err("ADXL363 not found".into());
+    }
+
+    let config = AdxlConfig::builder().build()?;
+    config.write(adxl)?;
+    config.set_measuring(adxl, true)?;
+    let result = self_test(adxl, clock, &config);
+    config.set_measuring(adxl, false)?;
+    Ok(result?)
 }
- 
+
+/// Time between temp_log rounds, whether or not a tag answered.
+const TEMP_LOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
  //temp_log uses ctrl+c to stop
 #[test]
 #[serial]
@@ -38,58 +4248,31 @@ fn temp_log() -> Result<(), Box<dyn Error>> {
 
     let gen2_cfg = Gen2Cfg::builder().build()?;
 
-    let config = Gen2Cfg::builder().build().unwrap();
-
     let mut reader = reader.configure_gen2(&gen2_cfg)?;
 
     reader.tune(TuningAlgorithm::Exact)?;
 
-    let mut csv_filename = String::new();
-
+    let clock = SystemClock::new();
 
     loop{
         if !running.load(Ordering::SeqCst){
             break;
         }
 
-        //getting the current date
-        let now = Local::now();
-        csv_filename = format!("temperature_log {}.csv", now.format("%Y-%m-%d")).to_string();
-        let now_str = now.format("%Y-%m-%d %H:%M:%S").to_string();
-        let temp = get_sensor_data(&mut reader)?;
-        let temperature_data = TemperatureData {
-            timestamp: now_str,
-            temperature: temp,
-        };
-
-        let mut csv_file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&csv_filename)?;
-
-        if csv_file.metadata()?.len() == 0 {
-            writeln!(&mut csv_file, "EPC, TID, Timestamp, Temperature (Celsius)")?;
-        }
-
         let (_, tags) = reader.inventory_once()?;
         println!("num of tags: {}", tags.len());
 
         if tags.is_empty() {
             println!("No tag found");
+            clock.sleep(TEMP_LOG_INTERVAL);
             continue;
         }
 
-        for tag in &tags {
-           writeln!(
-                &mut csv_file,
-                "{}, {}, {}, {}",
-                tag.epc,
-                tag.tid,
-                temperature_data.timestamp,
-                temperature_data.temperature
-           )?;
-        } 
-        std::thread::sleep(std::time::Duration::from_secs(5));
+        //each tag is selected and read on its own
+        let rows = read_tag_temperatures(&mut reader, &clock, &tags);
+        append_temperature_log(&clock, std::path::Path::new("."), &rows)?;
+
+        clock.sleep(TEMP_LOG_INTERVAL);
 
     }
 
@@ -97,7 +4280,7 @@ fn temp_log() -> Result<(), Box<dyn Error>> {
 }
 
 // new function that takes a specific epc for temp_log
-fn specific_temp_epc(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>>{
+fn specific_temp_epc<R: Gen2Transport>(reader: &mut R, clock: &dyn Clock, epc_to_find: HexID) -> Result<(), Box<dyn Error>>{
     println!("Executing temp_log function for individual EPC number");
 
      //atomic boolean to signal when to exit the program
@@ -107,8 +4290,6 @@ fn specific_temp_epc(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(),
     ctrlc::set_handler(move||{r.store(false, Ordering::SeqCst);
     }).expect("Error setting Ctrl+C handler");
         reader.tune(TuningAlgorithm::Exact)?;
-    
-        let mut csv_filename = String::new();
 
         //set to repeat 5 times right now, can change to loop
         //loop
@@ -116,49 +4297,17 @@ fn specific_temp_epc(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(),
             if !running.load(Ordering::SeqCst){
                 break;
             }
-            //getting time and naming csv file
-            let now = Local::now();
-            csv_filename = format!("temperature_log {}.csv", now.format("%Y-%m-%d")).to_string();
-            let now_str = now.format("%Y-%m-%d %H:%M:%S").to_string();
-            let temp = get_sensor_data(reader)?;
-            
-            let mut csv_file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&csv_filename)?;
-        
-            //creating header
-            if csv_file.metadata()?.len() == 0 {
-                writeln!(&mut csv_file, "EPC, TID, Timestamp, Temperature (Celsius)")?;
-            }
 
             //find epc number within tags
             let (_, tags) = reader.inventory_once()?;
-            let mut found_tag = None;
-            for tag in &tags {
-                if tag.epc == epc_to_find{
-                    found_tag = Some(tag);
-                    break;
-                }
-            }
-            //if it is found, create a temp structure and print info into csv
-            if let Some(tag) = found_tag{
-                reader.select(&tag.epc)?;
-                let temperature_data = TemperatureData {
-                    timestamp: now_str,
-                    temperature: temp,
-                };    
-                writeln!(
-                    &mut csv_file,
-                    "{}, {}, {}, {}",
-                    tag.epc,
-                    tag.tid,
-                    temperature_data.timestamp,
-                    temperature_data.temperature
-            )?;
+            let found: Vec<InventoryTag> = tags.into_iter().filter(|tag| tag.epc == epc_to_find).collect();
 
+            //if it is found, read that tag and log it
+            if !found.is_empty() {
+                let rows = read_tag_temperatures(reader, clock, &found[..1]);
+                append_temperature_log(clock, std::path::Path::new("."), &rows)?;
             }
-            std::thread::sleep(std::time::Duration::from_secs(5));
+            clock.sleep(std::time::Duration::from_secs(5));
 
     }
     Ok(())
@@ -166,276 +4315,63 @@ fn specific_temp_epc(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(),
 }
 
 //new funtion that takes a specific epc for em_sensor_test
-fn specific_sensor_test(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
+fn specific_sensor_test<R: Gen2Transport>(reader: &mut R, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
     println!("Executing em_sensor_test for individual EPC number");    
     println!("checking...");
     reader.select(&epc_to_find)?;
-    let temp = get_sensor_data(reader)?;
-    println!("Got temp: {temp} °C");
+    let data = read_sensor_data(reader)?;
+    println!("Got temp: {data}");
 
     Ok(())
         
 }
 
-//error with original function
 //new function that takes a specific epc for em_write_config
-fn specific_write_config(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
+fn specific_write_config<R: Gen2Transport>(reader: &mut R, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
     println!("Executing em_write_config for individual EPC number");
-    println!("checking..");
-    reader.select(&epc_to_find)?;
-    const TEMP_SENSOR_CONTROL_WORD_1: u32 = 0xEC;
-    const TEMP_SENSOR_CONTROL_WORD_2: u32 = 0xED;
-    const TEMP_SENSOR_CONTROL_WORD_3: u32 = 0xEE;
-    const IO_CONTROL_WORD: u32 = 0xF0;
-    const BATTERY_MANAGEMENT_WORD_1: u32 = 0xF1;
-    const BATTERY_MANAGEMENT_WORD_2: u32 = 0xF2;
-    const TOTAL_WORD: u32 = 0xF3;
-    const BAP_MODE_WORD: u32 = 0x10D;
-
-    println!("Seting up tag...");
-
-    println!("Writing temp sensor control words");
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_2,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_3,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    println!("Writing IO control word");
-
-    reader.write(MemoryBank::User, IO_CONTROL_WORD, [0x06, 0x00], None)?;
-
-    println!("Writing Battery Management control word");
-
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_2,
-        [0x00, 0x01],
-        None,
-    )?;
-
-    println!("Writing TOTAL word");
-
-    reader.write(MemoryBank::User, TOTAL_WORD, [0x00, 0x00], None)?;
-
-    println!("Writing BAP mode word");
-
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None)?;
-        
-
-
-    Ok(())
-}
-
-//error with original function
-//new function that takes a specific epc for em_bap_mode
-fn specific_bap_mode(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>>{
-    println!("Executing em_bap_mode for individual EPC number");
-
     reader.select(&epc_to_find)?;
-    const TEMP_SENSOR_CONTROL_WORD_1: u32 = 0xEC;
-    const TEMP_SENSOR_CONTROL_WORD_2: u32 = 0xED;
-    const TEMP_SENSOR_CONTROL_WORD_3: u32 = 0xEE;
-    const IO_CONTROL_WORD: u32 = 0xF0;
-    const BATTERY_MANAGEMENT_WORD_1: u32 = 0xF1;
-    const BATTERY_MANAGEMENT_WORD_2: u32 = 0xF2;
-    const TOTAL_WORD: u32 = 0xF3;
-    const BAP_MODE_WORD: u32 = 0x10D;
-
-    println!("Seting up tag...");
-
-    println!("Writing temp sensor control words");
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_2,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_3,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    println!("Writing IO control word");
-
-    reader.write(MemoryBank::User, IO_CONTROL_WORD, [0xE0, 0x00], None)?;
-
-    println!("Writing Battery Management control word");
-
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_1,
-        [0x20, 0x01],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_2,
-        [0x00, 0x01],
-        None,
-    )?;
 
-    println!("Writing TOTAL word");
+    apply_profile(reader, Em4325Profile::Default)?;
 
-    reader.write(MemoryBank::User, TOTAL_WORD, [0x00, 0x00], None)?;
-
-    println!("Writing BAP mode word");
-
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x01], None)?;
-   
     Ok(())
-}
-
-//error with original function
-//new function that takes a specific epc for em_passive_mode
-fn specific_passive_mode(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>>{
-    println!("Executing em_passive_mode for individual EPC number");
-    reader.select(&epc_to_find)?;
-
-    const TEMP_SENSOR_CONTROL_WORD_1: u32 = 0xEC;
-    const TEMP_SENSOR_CONTROL_WORD_2: u32 = 0xED;
-    const TEMP_SENSOR_CONTROL_WORD_3: u32 = 0xEE;
-    const IO_CONTROL_WORD: u32 = 0xF0;
-    const BATTERY_MANAGEMENT_WORD_1: u32 = 0xF1;
-    const BATTERY_MANAGEMENT_WORD_2: u32 = 0xF2;
-    const TOTAL_WORD: u32 = 0xF3;
-    const BAP_MODE_WORD: u32 = 0x10D;
-
-    println!("Seting up tag...");
-
-    println!("Writing temp sensor control words");
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_2,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_3,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    println!("Writing IO control word");
-
-    reader.write(MemoryBank::User, IO_CONTROL_WORD, [0xE6, 0x00], None)?;
-
-    println!("Writing Battery Management control word");
-
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_2,
-        [0x00, 0x01],
-        None,
-    )?;
+}
 
-    println!("Writing TOTAL word");
+//new function that takes a specific epc for em_bap_mode
+fn specific_bap_mode<R: Gen2Transport>(reader: &mut R, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
+    println!("Executing em_bap_mode for individual EPC number");
+    reader.select(&epc_to_find)?;
 
-    reader.write(MemoryBank::User, TOTAL_WORD, [0x00, 0x00], None)?;
+    apply_profile(reader, Em4325Profile::Bap)?;
 
-    println!("Writing BAP mode word");
+    Ok(())
+}
 
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None)?;
+//new function that takes a specific epc for em_passive_mode
+fn specific_passive_mode<R: Gen2Transport>(reader: &mut R, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
+    println!("Executing em_passive_mode for individual EPC number");
+    reader.select(&epc_to_find)?;
 
-    println!("Rewriting Battery Management Word 2");
+    apply_profile(reader, Em4325Profile::Passive)?;
 
-    // disable BAP control after writing BAP mode word
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_2,
-        [0x00, 0x00],
-        None,
-    )?;
-    
     Ok(())
 }
 
 //new function that takes a specific epc for em_read_config
-fn specific_read_config(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
+fn specific_read_config<R: Gen2Transport>(reader: &mut R, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
     println!("Executing em_read_config for individual EPC number");
 
     reader.select(&epc_to_find)?;
 
-    const TEMP_SENSOR_CONTROL_WORD: u32 = 0xEC;
-    const IO_CONTROL_WORD: u32 = 0xF0;
-    const BATTERY_MANAGEMENT_WORD: u32 = 0xF1;
-    const TOTAL_WORD: u32 = 0xF3;
-    const BAP_MODE_WORD: u32 = 0x10D;
-
     println!("Reading Tag Settings...");
 
-    let bytes = reader.read_alt(MemoryBank::User, TEMP_SENSOR_CONTROL_WORD, 3, None)?;
-    println!("Temp Sensor Control Words: {bytes:02X?}");
-
-    let bytes = reader.read_alt(MemoryBank::User, IO_CONTROL_WORD, 1, None)?;
-    println!("I/O Control Word: {bytes:02X?}");
-
-    let bytes = reader.read_alt(MemoryBank::User, BATTERY_MANAGEMENT_WORD, 2, None)?;
-    println!("Battery Management Words: {bytes:02X?}");
-
-    let bytes = reader.read_alt(MemoryBank::User, TOTAL_WORD, 1, None)?;
-    println!("TOTAL Words: {bytes:02X?}");
-
-    let bytes = reader.read_alt(MemoryBank::User, BAP_MODE_WORD, 1, None)?;
-    println!("BAP Mode Word: {bytes:02X?}");
+    let config = Em4325Config::read(reader)?;
+    println!("{config}");
     
     Ok(())
 }
 
 //new function that takes a specific epc for em_verify_calibration
-fn specific_verify_calibration(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
+fn specific_verify_calibration<R: Gen2Transport>(reader: &mut R, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
     reader.select(&epc_to_find)?;
 
     println!("Reading temperature sensor calibration words");
@@ -466,17 +4402,40 @@ fn specific_verify_calibration(reader: &mut Gen2Reader, epc_to_find: HexID) -> R
 }
 
 //new function for purple tags that takes a specific epc for adxl_setup_config
-fn specific_adxl_setup(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
+fn specific_adxl_setup<R: Gen2Transport + AdxlAccess>(reader: &mut R, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
     reader.select(&epc_to_find)?;
 
-    assert!(adxl::test_adxl_connection(reader)?);
+    if !reader.connected()? {
+        return Err("ADXL363 not found".into());
+    }
 
     println!("Setting up tag...");
-    Ok(adxl::setup(reader)?)
+    Ok(reader.setup()?)
+}
+
+/// Reads the FIFO words left after measuring, discarding the 3 words the
+/// part stores while it settles, and groups them into X, Y, Z samples.
+fn read_fifo_samples<A: AdxlAccess>(adxl: &mut A) -> Result<Vec<[i16; 3]>, Box<dyn Error>> {
+    let num_samples = adxl.fifo_entries()?;
+
+    // the first 3 samples are invalid data created by the setup process
+    if num_samples < 3 {
+        return Err(format!("only {num_samples} FIFO entries, expected the 3 settling words").into());
+    }
+    for _ in 0..3 {
+        adxl.read_fifo(2)?;
+    }
+
+    let words = fifo_words(&adxl.read_fifo((num_samples - 3) * 2)?);
+    Ok(fifo_samples(&words))
 }
 
 //new function for purple tags that takes a specific epc for adxl_sensor_test
-fn specific_adxl_sensor(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
+fn specific_adxl_sensor<R: Gen2Transport + AdxlAccess>(
+    reader: &mut R,
+    clock: &dyn Clock,
+    epc_to_find: HexID,
+) -> Result<Vec<[i16; 3]>, Box<dyn Error>> {
     reader.select(&epc_to_find)?;
 
     println!("Charging up semi-BAP");
@@ -485,53 +4444,48 @@ fn specific_adxl_sensor(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(
 
     println!("Checking ADXL connection...");
 
-    assert!(adxl::test_adxl_connection(reader)?);
+    if !reader.connected()? {
+        return Err("ADXL363 not found".into());
+    }
 
     println!("Configuring ADXL...");
 
-    adxl::setup(reader)?;
+    reader.setup()?;
 
     println!("Turning on measurements...");
 
-    adxl::turn_on(reader)?;
+    reader.set_measuring(true)?;
 
     // wait for 3 measurements to be taken
-    let stime = std::time::Instant::now();
+    let stime = clock.elapsed();
     let duration = std::time::Duration::from_secs_f32(3.0 / 12.5);
 
-    while stime.elapsed() < duration {
+    while clock.elapsed() - stime < duration {
         reader.inventory(20, Box::new(|_| {}))?;
+        clock.sleep(FIELD_POLL_INTERVAL);
     }
     reader.inventory_once()?;
 
     println!("Turning off measurements...");
 
-    adxl::turn_off(reader)?;
-
-    let num_samples = adxl::get_num_fifo_entries(reader)?;
-
-    println!("Got {num_samples} samples (discarding 3)");
-
-    assert!(num_samples >= 3);
-
-    println!("Reading {} measurements", (num_samples - 3) / 3);
-
-    // discard first 3 samples in fifo
-    for _ in 0..3 {
-        adxl::read_fifo(reader, 2)?;
-    }
+    reader.set_measuring(false)?;
 
     // Read all the measurements we got
-    let samples = adxl::get_fifo_entries(reader)?;
-    for (i, sample) in samples.iter().enumerate() {
-        println!("Sample {i}: {sample}")
+    let samples = read_fifo_samples(reader)?;
+    println!("Read {} measurements (first 3 FIFO words discarded)", samples.len());
+    for (i, [x, y, z]) in samples.iter().enumerate() {
+        println!("Sample {i}: {x} {y} {z}")
     }
 
-    Ok(())
+    Ok(samples)
 }
 
 //new function for purple tags that takes a specific epc for improved_vibration
-fn specific_improved_vibration(reader: &mut Gen2Reader, epc_to_find: HexID) -> Result<(), Box<dyn Error>> {
+fn specific_improved_vibration<R: Gen2Transport + AdxlAccess>(
+    reader: &mut R,
+    clock: &dyn Clock,
+    epc_to_find: HexID,
+) -> Result<Vec<[i16; 3]>, Box<dyn Error>> {
     reader.select(&epc_to_find)?;
 
     // Prepare test
@@ -541,92 +4495,48 @@ fn specific_improved_vibration(reader: &mut Gen2Reader, epc_to_find: HexID) -> R
     let mut peak_rssi = stats.rssi_log_mean;
 
     println!("Checking ADXL connection...");
-    assert!(adxl::test_adxl_connection(reader)?);
+    if !reader.connected()? {
+        return Err("ADXL363 not found".into());
+    }
 
     println!("Configuring ADXL...");
-    adxl::setup(reader)?;
+    reader.setup()?;
+    let adxl_config = vibration_config();
+    adxl_config.write(reader)?;
 
     // Actual Reading
     println!("Turning on measurements...");
-    adxl::turn_on(reader)?;
+    adxl_config.set_measuring(reader, true)?;
 
     // wait for 3 measurements to be taken
-    let stime_chrono = chrono::Utc::now();
-    let stime = std::time::Instant::now();
-    let duration = std::time::Duration::from_secs_f32(3.0 / 12.5);
-    while stime.elapsed() < duration {
+    let stime_chrono = clock.now();
+    let stime = clock.elapsed();
+    let duration = std::time::Duration::from_secs_f32(3.0 / adxl_config.odr.hz());
+    while clock.elapsed() - stime < duration {
         let stats = reader.inventory(20, Box::new(|_| {}))?;
         peak_rssi = std::cmp::max(stats.rssi_log_mean, peak_rssi);
+        clock.sleep(FIELD_POLL_INTERVAL);
     }
     // reset Gen2 errors in firmware
     reader.inventory_once()?;
 
     // End reading
     println!("Turning off measurements...");
-    adxl::turn_off(reader)?;
-
-    let num_samples = adxl::get_num_fifo_entries(reader)?;
-
-    // the first 3 samples are invalid data created by the setup process
-    println!("Got {num_samples} samples (discarding 3)");
-    assert!(num_samples >= 3);
-    for _ in 0..3 {
-        adxl::read_fifo(reader, 2)?;
-    }
-
-    let reflected_i;
-    let reflected_q;
-
-    // Reflected power not implemented in the wrapper
-    unsafe {
-        use libstuhfl_sys as ffi;
+    adxl_config.set_measuring(reader, false)?;
 
-        let mut param = ffi::STUHFL_T_ST25RU3993_FreqReflectedPowerInfo {
-            frequency: 865000,
-            applyTunerSetting: true,
-            reflectedI: 0,
-            reflectedQ: 0,
-        };
-
-        let ret_code = ffi::Get_FreqReflectedPower(&mut param);
-        assert!(ret_code == 0); // error handling is normally contained in the wrapper
-
-        reflected_i = param.reflectedI;
-        reflected_q = param.reflectedQ;
-    }
+    let (reflected_i, reflected_q) = reader.reflected_power(865000)?;
 
     println!("Peak RSSI: {peak_rssi}, Reflected Power: {reflected_i} (i) {reflected_q} (q)");
 
-    assert!(num_samples % 3 == 0);
-    println!("Reading {} measurements", (num_samples - 3) / 3);
-
     // Read all the measurements we got
-    let samples = adxl::get_fifo_entries(reader)?;
-
-    let interval = (1000.0 / 12.5) as i64;
-
-    for i in 0..(num_samples as usize/ 3) {
-        let index = i*3;
-        if index+2 < samples.len(){
-        println!(
-            "[{}] {} {} {}",
-            stime_chrono + chrono::Duration::milliseconds(interval * (i as i64 + 1)),
-            samples[i * 3],
-            samples[i * 3 + 1],
-            samples[i * 3 + 2]
-        );
-        }else{
-            println!("error");
-            break;
-        }
-        
-    }
-
-    for (i, sample) in samples.iter().enumerate() {
-        println!("Sample {i}: {sample}");
+    let samples = read_fifo_samples(reader)?;
+    println!("Read {} measurements (first 3 FIFO words discarded)", samples.len());
+    let timestamps = sample_timestamps(stime_chrono, adxl_config.odr.hz(), samples.len());
+    for (timestamp, [x, y, z]) in timestamps.iter().zip(&samples) {
+        println!("[{timestamp}] {x} {y} {z}");
     }
 
-    Ok(())
+    Ok(samples)
 }
 
 #[test]
@@ -688,9 +4598,9 @@ fn select_tag() ->  Result<(), Box<dyn Error>> {
         println!("Select the function you would like to perform: 
             1: temp_log function
             2: em_sensor_test
-            3: Error: em_write_config
-            4: Error: em_bap_mode
-            5: Error: em_passive_mode
+            3: em_write_config
+            4: em_bap_mode
+            5: em_passive_mode
             6: em_read_config
             7: em_verify_calibration
             8: Purple Tag - adxl_setup_config
@@ -702,7 +4612,7 @@ fn select_tag() ->  Result<(), Box<dyn Error>> {
         io::stdin().read_line(&mut choose_test).expect("Failed to read input");
         //check if user input is valid
         match choose_test.trim(){
-             "1" => match specific_temp_epc(&mut reader, epc_to_find){
+             "1" => match specific_temp_epc(&mut reader, &SystemClock::new(), epc_to_find){
                 Ok(()) => {
                     println!("Temperature Log completed successfully");
                 },
@@ -783,8 +4693,8 @@ fn select_tag() ->  Result<(), Box<dyn Error>> {
                     assert!(false, "Error occurred: {}", err);
                 }
                 } 
-            "9" => match specific_adxl_sensor(&mut reader, epc_to_find){
-                Ok(()) => {
+            "9" => match specific_adxl_sensor(&mut reader, &SystemClock::new(), epc_to_find){
+                Ok(_) => {
                     println!("adxl_sensor_test completed successfully");
                 },
                 Err(err) =>{
@@ -793,8 +4703,8 @@ fn select_tag() ->  Result<(), Box<dyn Error>> {
                     assert!(false, "Error occurred: {}", err);
                 }
                 }  
-            "10" => match specific_improved_vibration(&mut reader, epc_to_find){
-                Ok(()) => {
+            "10" => match specific_improved_vibration(&mut reader, &SystemClock::new(), epc_to_find){
+                Ok(_) => {
                     println!("improved_vibration completed successfully");
                 },
                 Err(err) =>{
@@ -858,7 +4768,32 @@ fn find_tags() -> TestResult {
 
 #[test]
 #[serial]
-fn em_write_config() -> TestResult {
+fn em_write_config() -> Result<(), Box<dyn Error>> {
+    let reader = Reader::autoconnect()?;
+
+    let config = Gen2Cfg::builder().build().unwrap();
+
+    let mut reader = reader.configure_gen2(&config)?;
+
+    reader.tune(TuningAlgorithm::Exact)?;
+
+    let (_, tags) = reader.inventory_once()?;
+
+    if tags.is_empty() {
+        panic!("No tag found")
+    }
+
+    reader.select(&tags[0].epc)?;
+
+    let config = apply_profile(&mut reader, Em4325Profile::Default)?;
+    println!("{config}");
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn em_sensor_test() -> TestResult{
     let reader = Reader::autoconnect()?;
 
     let config = Gen2Cfg::builder().build().unwrap();
@@ -875,74 +4810,374 @@ fn em_write_config() -> TestResult {
 
     reader.select(&tags[0].epc)?;
 
-    const TEMP_SENSOR_CONTROL_WORD_1: u32 = 0xEC;
-    const TEMP_SENSOR_CONTROL_WORD_2: u32 = 0xED;
-    const TEMP_SENSOR_CONTROL_WORD_3: u32 = 0xEE;
-    const IO_CONTROL_WORD: u32 = 0xF0;
-    const BATTERY_MANAGEMENT_WORD_1: u32 = 0xF1;
-    const BATTERY_MANAGEMENT_WORD_2: u32 = 0xF2;
-    const TOTAL_WORD: u32 = 0xF3;
-    const BAP_MODE_WORD: u32 = 0x10D;
+    let data = read_sensor_data(&mut reader)?;
 
-    println!("Seting up tag...");
+    println!("Got temp: {data}");
+    assert!(data.valid, "tag returned a stale measurement");
 
-    println!("Writing temp sensor control words");
+    Ok(())
+}
 
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
+#[test]
+#[serial]
+fn em_bap_mode() -> Result<(), Box<dyn Error>> {
+    let reader = Reader::autoconnect()?;
+
+    let config = Gen2Cfg::builder().build().unwrap();
+
+    let mut reader = reader.configure_gen2(&config)?;
+
+    reader.tune(TuningAlgorithm::Exact)?;
+
+    let (_, tags) = reader.inventory_once()?;
+
+    if tags.is_empty() {
+        panic!("No tag found")
+    }
+
+    reader.select(&tags[0].epc)?;
+
+    let config = apply_profile(&mut reader, Em4325Profile::Bap)?;
+    println!("{config}");
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn em_passive_mode() -> Result<(), Box<dyn Error>> {
+    let reader = Reader::autoconnect()?;
+
+    let config = Gen2Cfg::builder().build().unwrap();
+
+    let mut reader = reader.configure_gen2(&config)?;
+
+    reader.tune(TuningAlgorithm::Exact)?;
+
+    let (_, tags) = reader.inventory_once()?;
+
+    if tags.is_empty() {
+        panic!("No tag found")
+    }
+
+    reader.select(&tags[0].epc)?;
+
+    let config = apply_profile(&mut reader, Em4325Profile::Passive)?;
+    println!("{config}");
+
+    Ok(())
+}
 
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_2,
-        [0x00, 0x00],
+#[test]
+#[serial]
+fn em_read_config() -> TestResult {
+    let reader = Reader::autoconnect()?;
+
+    let config = Gen2Cfg::builder().build().unwrap();
+
+    let mut reader = reader.configure_gen2(&config)?;
+
+    reader.tune(TuningAlgorithm::Exact)?;
+
+    let (_, tags) = reader.inventory_once()?;
+
+    if tags.is_empty() {
+        panic!("No tag found")
+    }
+
+    reader.select(&tags[0].epc)?;
+
+    println!("Reading Tag Settings...");
+
+    let config = Em4325Config::read(&mut reader)?;
+    println!("{config}");
+
+    Ok(())
+}
+
+/// Connects to the reader, tunes it and takes one inventory round. Panics if
+/// no tag answers, as the hardware tests have nothing to work on then.
+fn connect_tags() -> Gen2Result<(Gen2Reader, Vec<InventoryTag>)> {
+    let reader = Reader::autoconnect()?;
+    let config = Gen2Cfg::builder().build().unwrap();
+    let mut reader = reader.configure_gen2(&config)?;
+    reader.tune(TuningAlgorithm::Exact)?;
+
+    let (_, tags) = reader.inventory_once()?;
+    if tags.is_empty() {
+        panic!("No tag found")
+    }
+    Ok((reader, tags))
+}
+
+/// `connect_tags`, then selects the first tag found and returns it.
+fn connect_first_tag() -> Gen2Result<(Gen2Reader, InventoryTag)> {
+    let (mut reader, mut tags) = connect_tags()?;
+    let tag = tags.swap_remove(0);
+    reader.select(&tag.epc)?;
+    Ok((reader, tag))
+}
+
+#[test]
+#[serial]
+fn em_audit_config() -> TestResult {
+    let (mut reader, tags) = connect_tags()?;
+
+    println!("Auditing {} tags against the Default profile...", tags.len());
+
+    for tag in &tags {
+        match audit_tag(&mut reader, &tag.epc, Em4325Profile::Default) {
+            Ok(diffs) if diffs.is_empty() => println!("{}: OK", tag.epc),
+            Ok(diffs) => {
+                println!("{}: {} words differ", tag.epc, diffs.len());
+                for diff in diffs {
+                    println!("    {diff}");
+                }
+            }
+            Err(err) => println!("{}: could not read configuration: {err:?}", tag.epc),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn em_snapshot_config() -> Result<(), Box<dyn Error>> {
+    let (mut reader, tag) = connect_first_tag()?;
+
+    let snapshot = TagSnapshot::take(&mut reader, &SystemClock::new(), &tag.epc)?;
+    let filename = format!("tag_snapshot {}.json", snapshot.epc);
+    snapshot.save(&filename)?;
+    println!("Saved configuration of {} to {filename}", tag.epc);
+
+    // restore it straight away to check the file round-trips
+    let restored = TagSnapshot::load(&filename)?.restore(&mut reader, &tag.epc)?;
+    println!("{}", restored.config);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn em_verify_calibration() -> TestResult {
+    let reader = Reader::autoconnect()?;
+
+    let config = Gen2Cfg::builder().build().unwrap();
+
+    let mut reader = reader.configure_gen2(&config)?;
+
+    reader.tune(TuningAlgorithm::Exact)?;
+
+    let (_, tags) = reader.inventory_once()?;
+
+    if tags.is_empty() {
+        panic!("No tag found")
+    }
+
+    reader.select(&tags[0].epc)?;
+
+    println!("Reading temperature sensor calibration words");
+
+    let co = reader.read_alt(MemoryBank::Tid, 0x0D, 1, None)?;
+    let co_int = u16::from_be_bytes([co[0], co[1]]);
+
+    let cc = reader.read_alt(MemoryBank::User, 0xEF, 1, None)?;
+    let cc_int = u16::from_be_bytes([cc[0], cc[1]]);
+
+    // highest 11 bits must be identical
+    if co_int & 0xFFE0 != cc_int & 0xFFE0 {
+        println!("Error in temperature sensor calibration. Resetting...");
+        reader.write(MemoryBank::User, 0xEF, [co[0], co[1]], None)?;
+        println!("Done.")
+    } else {
+        println!("Temperature sensor calibration successfully verified.");
+        let offset = if cc_int & 0b0000_0000_0001_0000 != 0 {
+            cc_int | 0b1111_1111_1110_0000 // sign extension negative
+        } else {
+            cc_int & 0b0000_0000_0001_1111 // sign extension positive
+        };
+
+        println!("Fine trim value: {} °C", process_temp(offset));
+    }
+
+    Ok(())
+}
+
+/// Reference temperature for `em_calibrate_temperature`, read by a trusted
+/// thermometer next to the tag, in °C.
+const CALIBRATION_REFERENCE_VAR: &str = "EM4325_REFERENCE_C";
+
+// needs the tag at a known temperature, so it only runs on request:
+// EM4325_REFERENCE_C=21.5 cargo test em_calibrate_temperature -- --ignored
+#[test]
+#[serial]
+#[ignore = "needs a reference thermometer reading in EM4325_REFERENCE_C"]
+fn em_calibrate_temperature() -> Result<(), Box<dyn Error>> {
+    let reference: f32 = std::env::var(CALIBRATION_REFERENCE_VAR)
+        .map_err(|_| format!("set {CALIBRATION_REFERENCE_VAR} to the thermometer reading in °C"))?
+        .trim()
+        .parse()?;
+
+    let (mut reader, tag) = connect_first_tag()?;
+
+    let clock = SystemClock::new();
+    let epc = tag.epc;
+
+    let point = measure_calibration_point(&mut reader, &clock, reference)?;
+    println!("Tag reads {} °C at {reference} °C", point.measured);
+    let points = [point];
+
+    let record = calibrate_temperature(&mut reader, &clock, &epc, &points)?;
+    record.log("calibration_log.csv")?;
+
+    println!(
+        "Calibration word 0x{:04X} -> 0x{:04X}, remaining error {} °C",
+        record.word_before,
+        record.word_after,
+        record.residual()
+    );
+    assert!(record.confirmed(), "correction not confirmed by re-measurement");
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn em_temperature_alarms() -> Result<(), Box<dyn Error>> {
+    let (mut reader, _) = connect_first_tag()?;
+
+    // cold chain: 2-8 °C, sampled every minute, alarm after 3 bad samples
+    let alarms = TemperatureAlarms {
+        under: Some(2.0),
+        over: Some(8.0),
+        sample_interval: 1,
+        alarm_delay: 2,
+    };
+    let applied = set_temperature_alarms(&mut reader, alarms)?;
+    println!("Alarms set: {applied:?}");
+    assert_eq!(applied, alarms);
+
+    clear_temperature_alarms(&mut reader)?;
+    println!("{}", AlarmState::read(&mut reader)?);
+
+    Ok(())
+}
+
+/// The locked part of `em_lock_config`, kept apart so the test can undo the
+/// locks whether or not this succeeds.
+fn lock_and_report(reader: &mut Authenticated<Gen2Reader>, access: [u8; 4]) -> Result<(), Box<dyn Error>> {
+    lock_tag(
+        reader,
+        &[
+            (LockTarget::AccessPassword, LockAction::Lock),
+            (LockTarget::Epc, LockAction::Lock),
+            (LockTarget::User, LockAction::Lock),
+        ],
         None,
     )?;
+    let state = LockState::probe_with_writes(reader.inner_mut(), Some(access))?;
+    println!("Lock state: {state}");
+    println!("{}", Em4325Config::read(reader)?);
+    Ok(())
+}
 
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_3,
-        [0x00, 0x00],
+#[test]
+#[serial]
+fn em_lock_config() -> Result<(), Box<dyn Error>> {
+    let (mut reader, _) = connect_first_tag()?;
+
+    // reversible: lock (never permalock) and put everything back at the end
+    let access = [0x12, 0x34, 0x56, 0x78];
+    set_passwords(&mut reader, [0; 4], access, None)?;
+    let mut reader = Authenticated::new(reader, access);
+
+    // whatever fails while locked, the unlock and password reset still run
+    let locked = lock_and_report(&mut reader, access);
+
+    let unlocked = lock_tag(
+        &mut reader,
+        &[
+            (LockTarget::AccessPassword, LockAction::Unlock),
+            (LockTarget::Epc, LockAction::Unlock),
+            (LockTarget::User, LockAction::Unlock),
+        ],
         None,
-    )?;
+    );
+    let mut reader = reader.into_inner();
+    let reset = set_passwords(&mut reader, [0; 4], [0; 4], Some(access));
+
+    locked?;
+    unlocked?;
+    reset
+}
 
-    println!("Writing IO control word");
+#[test]
+#[serial]
+fn em_tamper_pin() -> Result<(), Box<dyn Error>> {
+    let (mut reader, _) = connect_first_tag()?;
 
-    reader.write(MemoryBank::User, IO_CONTROL_WORD, [0x06, 0x00], None)?;
+    let previous = read_word(&mut reader, IO_CONTROL_WORD)?;
+    let io = set_pin_mode(&mut reader, 0, PinMode::Aux)?;
+    println!("I/O Control: {io}");
+    clear_pin_events(&mut reader)?;
 
-    println!("Writing Battery Management control word");
+    println!("Open and close the door switch on P0, then press enter");
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).expect("Failed to read input");
 
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
+    let state = IoState::read(&mut reader)?;
+    let data = read_sensor_data(&mut reader)?;
+    println!("I/O state: {state}");
+    println!("Got temp: {data}");
+    println!("Door opened: {}", state.event(0));
 
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_2,
-        [0x00, 0x01],
-        None,
-    )?;
+    let step = ConfigStep {
+        name: word_name(IO_CONTROL_WORD),
+        address: IO_CONTROL_WORD,
+        value: previous,
+    };
+    apply_config_transaction(&mut reader, &[step])?;
 
-    println!("Writing TOTAL word");
+    Ok(())
+}
 
-    reader.write(MemoryBank::User, TOTAL_WORD, [0x00, 0x00], None)?;
+#[test]
+#[serial]
+fn em_simple_sensor_inventory() -> Result<(), Box<dyn Error>> {
+    let (mut reader, tags) = connect_tags()?;
+
+    let epc_len = hex_bytes(&tags[0].epc).len();
+    let epcs: Vec<HexID> = tags.iter().map(|tag| tag.epc.clone()).collect();
+    for reply in simple_sensor_round(&mut reader, &epcs, epc_len)? {
+        match reply.data {
+            Some(data) => println!("{}: {data}", reply.epc),
+            None => println!("{}: no sensor data", reply.epc),
+        }
+    }
 
-    println!("Writing BAP mode word");
+    Ok(())
+}
 
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None)?;
+#[test]
+#[serial]
+fn em_battery_health() -> Result<(), Box<dyn Error>> {
+    let (mut reader, tags) = connect_tags()?;
+
+    let clock = SystemClock::new();
+    for (epc, health) in battery_health_report(&mut reader, &clock, &tags, PseudoBapTiming::default()) {
+        match health {
+            Ok(health) => println!("{health}"),
+            Err(err) => println!("{epc}: couldn't be read ({err:?})"),
+        }
+    }
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn em_sensor_test() -> TestResult{
+fn adxl_read_test() -> TestResult {
     let reader = Reader::autoconnect()?;
 
     let config = Gen2Cfg::builder().build().unwrap();
@@ -959,16 +5194,21 @@ fn em_sensor_test() -> TestResult{
 
     reader.select(&tags[0].epc)?;
 
-    let temp = get_sensor_data(&mut reader)?;
+    println!("Reading ID Registers");
+    let bytes = reader.read_register(AdxlRegister::DevIdAd, 3)?;
+
+    println!("Got IDs: {:02X?}", &bytes);
+
+    assert_eq!(bytes, ADXL_ID);
 
-    println!("Got temp: {temp} °C");
+    println!("IDs are correct.");
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn em_bap_mode() -> TestResult {
+fn adxl_write_test() -> TestResult {
     let reader = Reader::autoconnect()?;
 
     let config = Gen2Cfg::builder().build().unwrap();
@@ -977,6 +5217,8 @@ fn em_bap_mode() -> TestResult {
 
     reader.tune(TuningAlgorithm::Exact)?;
 
+    let clock = SystemClock::new();
+
     let (_, tags) = reader.inventory_once()?;
 
     if tags.is_empty() {
@@ -985,74 +5227,30 @@ fn em_bap_mode() -> TestResult {
 
     reader.select(&tags[0].epc)?;
 
-    const TEMP_SENSOR_CONTROL_WORD_1: u32 = 0xEC;
-    const TEMP_SENSOR_CONTROL_WORD_2: u32 = 0xED;
-    const TEMP_SENSOR_CONTROL_WORD_3: u32 = 0xEE;
-    const IO_CONTROL_WORD: u32 = 0xF0;
-    const BATTERY_MANAGEMENT_WORD_1: u32 = 0xF1;
-    const BATTERY_MANAGEMENT_WORD_2: u32 = 0xF2;
-    const TOTAL_WORD: u32 = 0xF3;
-    const BAP_MODE_WORD: u32 = 0x10D;
-
-    println!("Seting up tag...");
-
-    println!("Writing temp sensor control words");
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_2,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_3,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    println!("Writing IO control word");
-
-    reader.write(MemoryBank::User, IO_CONTROL_WORD, [0xE0, 0x00], None)?;
+    let data = &[0x12, 0x34];
 
-    println!("Writing Battery Management control word");
+    let address = AdxlRegister::TimeInactL;
 
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_1,
-        [0x20, 0x01],
-        None,
-    )?;
+    let backup = reader.read_register(address, data.len() as u16)?;
 
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_2,
-        [0x00, 0x01],
-        None,
-    )?;
+    println!("Writing Register");
+    reader.write_register(address, data)?;
 
-    println!("Writing TOTAL word");
+    clock.sleep(std::time::Duration::from_millis(100));
 
-    reader.write(MemoryBank::User, TOTAL_WORD, [0x00, 0x00], None)?;
+    let new = reader.read_register(address, data.len() as u16)?;
 
-    println!("Writing BAP mode word");
+    assert_eq!(new, data);
 
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x01], None)?;
+    println!("Resetting Register");
+    reader.write_register(address, &backup)?;
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn em_passive_mode() -> TestResult {
+fn adxl_setup_config() -> Result<(), Box<dyn Error>> {
     let reader = Reader::autoconnect()?;
 
     let config = Gen2Cfg::builder().build().unwrap();
@@ -1067,86 +5265,38 @@ fn em_passive_mode() -> TestResult {
         panic!("No tag found")
     }
 
-    reader.select(&tags[0].epc)?;
-
-    const TEMP_SENSOR_CONTROL_WORD_1: u32 = 0xEC;
-    const TEMP_SENSOR_CONTROL_WORD_2: u32 = 0xED;
-    const TEMP_SENSOR_CONTROL_WORD_3: u32 = 0xEE;
-    const IO_CONTROL_WORD: u32 = 0xF0;
-    const BATTERY_MANAGEMENT_WORD_1: u32 = 0xF1;
-    const BATTERY_MANAGEMENT_WORD_2: u32 = 0xF2;
-    const TOTAL_WORD: u32 = 0xF3;
-    const BAP_MODE_WORD: u32 = 0x10D;
-
-    println!("Seting up tag...");
-
-    println!("Writing temp sensor control words");
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_2,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_3,
-        [0x00, 0x00],
-        None,
-    )?;
-
-    println!("Writing IO control word");
-
-    reader.write(MemoryBank::User, IO_CONTROL_WORD, [0xE6, 0x00], None)?;
-
-    println!("Writing Battery Management control word");
+    let epc = tags[0].epc.clone();
+    specific_adxl_setup(&mut reader, epc)
+}
 
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
+#[test]
+#[serial]
+fn adxl_sensor_test() -> Result<(), Box<dyn Error>> {
+    let reader = Reader::autoconnect()?;
 
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_2,
-        [0x00, 0x01],
-        None,
-    )?;
+    let config = Gen2Cfg::builder().build().unwrap();
 
-    println!("Writing TOTAL word");
+    let mut reader = reader.configure_gen2(&config)?;
 
-    reader.write(MemoryBank::User, TOTAL_WORD, [0x00, 0x00], None)?;
+    reader.tune(TuningAlgorithm::Exact)?;
 
-    println!("Writing BAP mode word");
+    let clock = SystemClock::new();
 
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None)?;
+    let (_, tags) = reader.inventory_once()?;
 
-    println!("Rewriting Battery Management Word 2");
+    if tags.is_empty() {
+        panic!("No tag found")
+    }
 
-    // disable BAP control after writing BAP mode word
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_2,
-        [0x00, 0x00],
-        None,
-    )?;
+    let epc = tags[0].epc.clone();
+    specific_adxl_sensor(&mut reader, &clock, epc)?;
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn em_read_config() -> TestResult {
+fn adxl_self_test() -> Result<(), Box<dyn Error>> {
     let reader = Reader::autoconnect()?;
 
     let config = Gen2Cfg::builder().build().unwrap();
@@ -1155,6 +5305,8 @@ fn em_read_config() -> TestResult {
 
     reader.tune(TuningAlgorithm::Exact)?;
 
+    let clock = SystemClock::new();
+
     let (_, tags) = reader.inventory_once()?;
 
     if tags.is_empty() {
@@ -1163,81 +5315,39 @@ fn em_read_config() -> TestResult {
 
     reader.select(&tags[0].epc)?;
 
-    const TEMP_SENSOR_CONTROL_WORD: u32 = 0xEC;
-    const IO_CONTROL_WORD: u32 = 0xF0;
-    const BATTERY_MANAGEMENT_WORD: u32 = 0xF1;
-    const TOTAL_WORD: u32 = 0xF3;
-    const BAP_MODE_WORD: u32 = 0x10D;
-
-    println!("Reading Tag Settings...");
-
-    let bytes = reader.read_alt(MemoryBank::User, TEMP_SENSOR_CONTROL_WORD, 3, None)?;
-    println!("Temp Sensor Control Words: {bytes:02X?}");
-
-    let bytes = reader.read_alt(MemoryBank::User, IO_CONTROL_WORD, 1, None)?;
-    println!("I/O Control Word: {bytes:02X?}");
-
-    let bytes = reader.read_alt(MemoryBank::User, BATTERY_MANAGEMENT_WORD, 2, None)?;
-    println!("Battery Management Words: {bytes:02X?}");
+    let result = commission_adxl(&mut reader, &clock)?;
 
-    let bytes = reader.read_alt(MemoryBank::User, TOTAL_WORD, 1, None)?;
-    println!("TOTAL Words: {bytes:02X?}");
-
-    let bytes = reader.read_alt(MemoryBank::User, BAP_MODE_WORD, 1, None)?;
-    println!("BAP Mode Word: {bytes:02X?}");
+    println!("Self test: {result}");
+    assert!(result.passed(), "ADXL363 failed self test");
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn em_verify_calibration() -> TestResult {
+fn improved_vibration() -> Result<(), Box<dyn Error>> {
+    // Initial setup
     let reader = Reader::autoconnect()?;
-
     let config = Gen2Cfg::builder().build().unwrap();
-
     let mut reader = reader.configure_gen2(&config)?;
+    let clock = SystemClock::new();
 
+    // Scanning for tags
     reader.tune(TuningAlgorithm::Exact)?;
-
     let (_, tags) = reader.inventory_once()?;
-
     if tags.is_empty() {
         panic!("No tag found")
     }
-
-    reader.select(&tags[0].epc)?;
-
-    println!("Reading temperature sensor calibration words");
-
-    let co = reader.read_alt(MemoryBank::Tid, 0x0D, 1, None)?;
-    let co_int = u16::from_be_bytes([co[0], co[1]]);
-
-    let cc = reader.read_alt(MemoryBank::User, 0xEF, 1, None)?;
-    let cc_int = u16::from_be_bytes([cc[0], cc[1]]);
-
-    // highest 11 bits must be identical
-    if co_int & 0xFFE0 != cc_int & 0xFFE0 {
-        println!("Error in temperature sensor calibration. Resetting...");
-        reader.write(MemoryBank::User, 0xEF, [co[0], co[1]], None)?;
-        println!("Done.")
-    } else {
-        println!("Temperature sensor calibration successfully verified.");
-        let offset = if cc_int & 0b0000_0000_0001_0000 != 0 {
-            cc_int | 0b1111_1111_1110_0000 // sign extension negative
-        } else {
-            cc_int & 0b0000_0000_0001_1111 // sign extension positive
-        };
-
-        println!("Fine trim value: {} °C", process_temp(offset));
-    }
+    let epc = tags[0].epc.clone();
+    println!("Selecting tag with EPC {epc}");
+    specific_improved_vibration(&mut reader, &clock, epc)?;
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn adxl_read_test() -> TestResult {
+fn em_pseudo_bap_mode() -> Result<(), Box<dyn Error>> {
     let reader = Reader::autoconnect()?;
 
     let config = Gen2Cfg::builder().build().unwrap();
@@ -1254,61 +5364,346 @@ fn adxl_read_test() -> TestResult {
 
     reader.select(&tags[0].epc)?;
 
-    println!("Reading ID Registers");
-    let bytes = adxl::read_register(&mut reader, adxl::Register::DevIdAd, 3)?;
+    let config = apply_profile(&mut reader, Em4325Profile::PseudoBap)?;
+    println!("{config}");
 
-    println!("Got IDs: {:02X?}", &bytes);
+    Ok(())
+}
 
-    assert_eq!(bytes, &[0xAD, 0x1D, 0xF3]);
+/// Phases of a pseudo-BAP measurement, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PseudoBapPhase {
+    /// Enable BAP and leave the field off so the storage capacitor drains.
+    Discharge,
+    /// Keep retrying the BAP-off write until the capacitor holds enough
+    /// charge for the tag to answer.
+    Charge,
+    /// Power-cycle and switch BAP on to start the measurement.
+    Trigger,
+    /// Clear the sensor data and wait out the field-off effects.
+    Settle,
+    /// Read the sensor data words.
+    Read,
+    /// Switch BAP off again.
+    Stop,
+}
 
-    println!("IDs are correct.");
+impl PseudoBapPhase {
+    fn next(self) -> Option<Self> {
+        match self {
+            PseudoBapPhase::Discharge => Some(PseudoBapPhase::Charge),
+            PseudoBapPhase::Charge => Some(PseudoBapPhase::Trigger),
+            PseudoBapPhase::Trigger => Some(PseudoBapPhase::Settle),
+            PseudoBapPhase::Settle => Some(PseudoBapPhase::Read),
+            PseudoBapPhase::Read => Some(PseudoBapPhase::Stop),
+            PseudoBapPhase::Stop => None,
+        }
+    }
+}
 
-    Ok(())
+impl std::fmt::Display for PseudoBapPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            PseudoBapPhase::Discharge => "discharging capacitor",
+            PseudoBapPhase::Charge => "charging capacitor",
+            PseudoBapPhase::Trigger => "initiating measurement",
+            PseudoBapPhase::Settle => "allowing field-off effects",
+            PseudoBapPhase::Read => "reading measurement",
+            PseudoBapPhase::Stop => "stopping measurement",
+        };
+        write!(f, "{name}")
+    }
 }
 
-#[test]
-#[serial]
-fn adxl_write_test() -> TestResult {
-    let reader = Reader::autoconnect()?;
+/// Delays and limits for a pseudo-BAP measurement.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PseudoBapTiming {
+    /// Field-off pause, with an inventory after it, to power-cycle the tag.
+    power_cycle: std::time::Duration,
+    /// How long the capacitor is left to drain.
+    discharge: std::time::Duration,
+    /// Pause between charge attempts.
+    charge_retry: std::time::Duration,
+    /// How long after the measurement starts before the data is read.
+    settle: std::time::Duration,
+    /// Longest any single phase may take, waits included. Every phase checks
+    /// it after each wait and stops as soon as it is overrun; charging gives
+    /// up once it is reached.
+    phase_timeout: std::time::Duration,
+}
 
-    let config = Gen2Cfg::builder().build().unwrap();
+impl Default for PseudoBapTiming {
+    fn default() -> Self {
+        Self {
+            power_cycle: std::time::Duration::from_millis(500),
+            discharge: std::time::Duration::from_secs(20),
+            charge_retry: std::time::Duration::from_millis(20),
+            settle: std::time::Duration::from_millis(2500),
+            phase_timeout: std::time::Duration::from_secs(30),
+        }
+    }
+}
 
-    let mut reader = reader.configure_gen2(&config)?;
+/// Progress reported while a pseudo-BAP measurement runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PseudoBapEvent {
+    Started(PseudoBapPhase),
+    /// A charge write went unanswered; `attempt` counts from 1.
+    ChargeRetry { attempt: u32 },
+    /// The first charge write was answered, `after` the charge phase started.
+    Charged { after: std::time::Duration },
+    Finished(PseudoBapPhase, std::time::Duration),
+}
 
-    reader.tune(TuningAlgorithm::Exact)?;
+#[derive(Debug)]
+enum PseudoBapFailure {
+    Reader(StuhflError),
+    Timeout(std::time::Duration),
+    /// The tag answered but had no completed measurement.
+    Stale(SensorData),
+}
 
-    let (_, tags) = reader.inventory_once()?;
+impl From<StuhflError> for PseudoBapFailure {
+    fn from(err: StuhflError) -> Self {
+        PseudoBapFailure::Reader(err)
+    }
+}
 
-    if tags.is_empty() {
-        panic!("No tag found")
+/// A pseudo-BAP measurement that stopped in `phase`.
+#[derive(Debug)]
+struct PseudoBapError {
+    phase: PseudoBapPhase,
+    failure: PseudoBapFailure,
+}
+
+impl std::fmt::Display for PseudoBapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.failure {
+            PseudoBapFailure::Reader(err) => write!(f, "pseudo-BAP failed while {}: {err:?}", self.phase),
+            PseudoBapFailure::Timeout(after) => {
+                write!(f, "pseudo-BAP timed out while {} after {after:?}", self.phase)
+            }
+            PseudoBapFailure::Stale(data) => {
+                write!(f, "pseudo-BAP failed while {}: stale sensor data {data}", self.phase)
+            }
+        }
     }
+}
 
-    reader.select(&tags[0].epc)?;
+impl Error for PseudoBapError {}
+
+/// A pseudo-BAP temperature measurement on the selected tag, run one phase at
+/// a time: discharge the capacitor, charge it from the field, trigger a
+/// measurement with BAP mode and read it back once the field-off effects have
+/// settled. All waiting is done by sleeping on the clock.
+struct PseudoBapMeasurement {
+    timing: PseudoBapTiming,
+    phase: Option<PseudoBapPhase>,
+    data: Option<SensorData>,
+    /// Charge writes it took before the tag answered.
+    charge_attempts: u32,
+}
 
-    let data = &[0x12, 0x34];
+impl PseudoBapMeasurement {
+    fn new(timing: PseudoBapTiming) -> Self {
+        Self {
+            timing,
+            phase: Some(PseudoBapPhase::Discharge),
+            data: None,
+            charge_attempts: 0,
+        }
+    }
 
-    let address = adxl::Register::TimeInactL;
+    /// The phase `step` will run next, or `None` once the measurement is done.
+    fn phase(&self) -> Option<PseudoBapPhase> {
+        self.phase
+    }
 
-    let backup = adxl::read_register(&mut reader, address, data.len() as u16)?;
+    /// Runs the current phase and moves on to the next.
+    fn step<R: Gen2Transport>(
+        &mut self,
+        reader: &mut R,
+        clock: &dyn Clock,
+        on_event: &mut dyn FnMut(PseudoBapEvent),
+    ) -> Result<(), PseudoBapError> {
+        let Some(phase) = self.phase else {
+            return Ok(());
+        };
+        let started = clock.elapsed();
+        on_event(PseudoBapEvent::Started(phase));
 
-    println!("Writing Register");
-    adxl::write_register(&mut reader, address, data)?;
+        self.run_phase(phase, started, reader, clock, on_event)
+            .map_err(|failure| PseudoBapError { phase, failure })?;
 
-    std::thread::sleep(std::time::Duration::from_millis(100));
+        on_event(PseudoBapEvent::Finished(phase, clock.elapsed() - started));
+        self.phase = phase.next();
+        Ok(())
+    }
 
-    let new = adxl::read_register(&mut reader, address, data.len() as u16)?;
+    /// Runs `phase`, which began at `started` on the clock.
+    fn run_phase<R: Gen2Transport>(
+        &mut self,
+        phase: PseudoBapPhase,
+        started: std::time::Duration,
+        reader: &mut R,
+        clock: &dyn Clock,
+        on_event: &mut dyn FnMut(PseudoBapEvent),
+    ) -> Result<(), PseudoBapFailure> {
+        let t = self.timing;
+        let overrun = || -> Result<(), PseudoBapFailure> {
+            let elapsed = clock.elapsed() - started;
+            if elapsed > t.phase_timeout {
+                return Err(PseudoBapFailure::Timeout(elapsed));
+            }
+            Ok(())
+        };
+        // delay & inventory to power-cycle
+        let power_cycle = |reader: &mut R| -> Result<(), PseudoBapFailure> {
+            clock.sleep(t.power_cycle);
+            reader.inventory_once()?;
+            overrun()
+        };
 
-    assert_eq!(new, data);
+        match phase {
+            PseudoBapPhase::Discharge => {
+                reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x01], None)?;
+                power_cycle(reader)?;
+                clock.sleep(t.discharge);
+                overrun()?;
+            }
+            PseudoBapPhase::Charge => {
+                self.charge_attempts = 0;
+                loop {
+                    self.charge_attempts += 1;
+                    match reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None) {
+                        Ok(()) => {
+                            on_event(PseudoBapEvent::Charged { after: clock.elapsed() - started });
+                            break;
+                        }
+                        Err(_) if clock.elapsed() - started < t.phase_timeout => {
+                            on_event(PseudoBapEvent::ChargeRetry { attempt: self.charge_attempts });
+                            clock.sleep(t.charge_retry);
+                        }
+                        Err(_) => return Err(PseudoBapFailure::Timeout(clock.elapsed() - started)),
+                    }
+                }
+                reader.inventory(200, Box::new(|_| ())).ok();
+                overrun()?;
+            }
+            PseudoBapPhase::Trigger => {
+                power_cycle(reader)?;
+                reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x01], None)?;
+                power_cycle(reader)?;
+            }
+            PseudoBapPhase::Settle => {
+                reader.write(MemoryBank::User, SENSOR_DATA_MSW, [0x00, 0x00], None)?;
+                clock.sleep(t.settle);
+                overrun()?;
+            }
+            PseudoBapPhase::Read => {
+                let data = read_sensor_data(reader)?;
+                if !data.valid {
+                    return Err(PseudoBapFailure::Stale(data));
+                }
+                self.data = Some(data);
+            }
+            PseudoBapPhase::Stop => {
+                reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None)?;
+                clock.sleep(t.power_cycle);
+                reader.inventory(200, Box::new(|_| ()))?;
+                overrun()?;
+            }
+        }
+        Ok(())
+    }
 
-    println!("Resetting Register");
-    adxl::write_register(&mut reader, address, &backup)?;
+    /// Runs every remaining phase. If any phase fails, BAP is switched off
+    /// again on a best-effort basis before returning: even the discharge
+    /// switches it on, and a write whose reply was lost may still have landed.
+    fn run<R: Gen2Transport>(
+        mut self,
+        reader: &mut R,
+        clock: &dyn Clock,
+        on_event: &mut dyn FnMut(PseudoBapEvent),
+    ) -> Result<SensorData, PseudoBapError> {
+        while self.phase.is_some() {
+            if let Err(err) = self.step(reader, clock, on_event) {
+                reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None).ok();
+                return Err(err);
+            }
+        }
+        Ok(self.data.expect("read phase stores the sensor data"))
+    }
+}
 
-    Ok(())
+/// Takes one pseudo-BAP measurement on the selected tag with the default
+/// timing, printing progress.
+fn pseudo_bap_measurement<R: Gen2Transport>(
+    reader: &mut R,
+    clock: &dyn Clock,
+) -> Result<SensorData, PseudoBapError> {
+    PseudoBapMeasurement::new(PseudoBapTiming::default()).run(reader, clock, &mut |event| match event {
+        PseudoBapEvent::Started(phase) => println!("{phase}..."),
+        PseudoBapEvent::ChargeRetry { attempt } if attempt % 100 == 0 => println!("still charging, {attempt} attempts"),
+        PseudoBapEvent::ChargeRetry { .. } => {}
+        PseudoBapEvent::Charged { after } => println!("capacitor charged after {after:?}"),
+        PseudoBapEvent::Finished(phase, elapsed) => println!("done {phase} in {elapsed:?}"),
+    })
+}
+
+/// One row of a scheduled pseudo-BAP log.
+#[derive(Debug)]
+struct PseudoBapSample {
+    timestamp: chrono::DateTime<chrono::Utc>,
+    result: Result<SensorData, PseudoBapError>,
+}
+
+/// Takes `count` pseudo-BAP measurements of `epc`, one every `interval`, and
+/// appends each to the CSV at `path`. A failed measurement is logged with the
+/// phase it failed in and the schedule carries on.
+fn log_pseudo_bap<R: Gen2Transport>(
+    reader: &mut R,
+    clock: &dyn Clock,
+    epc: &HexID,
+    timing: PseudoBapTiming,
+    interval: std::time::Duration,
+    count: usize,
+    path: impl AsRef<std::path::Path>,
+) -> Result<Vec<PseudoBapSample>, Box<dyn Error>> {
+    let mut csv_file = OpenOptions::new().append(true).create(true).open(path)?;
+    if csv_file.metadata()?.len() == 0 {
+        writeln!(&mut csv_file, "EPC, Timestamp, Temperature (Celsius), Sequence, Status")?;
+    }
+
+    let mut samples = Vec::new();
+    for i in 0..count {
+        let due = interval * i as u32;
+        if let Some(wait) = due.checked_sub(clock.elapsed()) {
+            clock.sleep(wait);
+        }
+
+        let timestamp = clock.now();
+        let result = reader
+            .select(epc)
+            .map_err(|err| PseudoBapError {
+                phase: PseudoBapPhase::Discharge,
+                failure: PseudoBapFailure::Reader(err),
+            })
+            .and_then(|()| PseudoBapMeasurement::new(timing).run(reader, clock, &mut |_| ()));
+
+        let time = timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+        match &result {
+            Ok(data) => writeln!(&mut csv_file, "{epc}, {time}, {}, {}, ok", data.temperature, data.sequence)?,
+            Err(err) => writeln!(&mut csv_file, "{epc}, {time}, , , failed while {}", err.phase)?,
+        }
+        samples.push(PseudoBapSample { timestamp, result });
+    }
+    Ok(samples)
 }
 
 #[test]
 #[serial]
-fn adxl_setup_config() -> TestResult {
+fn em_pseudo_bap_test() -> Result<(), Box<dyn Error>> {
     let reader = Reader::autoconnect()?;
 
     let config = Gen2Cfg::builder().build().unwrap();
@@ -1325,403 +5720,1448 @@ fn adxl_setup_config() -> TestResult {
 
     reader.select(&tags[0].epc)?;
 
-    assert!(adxl::test_adxl_connection(&mut reader)?);
+    let measurement = pseudo_bap_measurement(&mut reader, &SystemClock::new())?;
 
-    println!("Setting up tag...");
-    adxl::setup(&mut reader)
+    println!("Got temperature: {measurement}");
+
+    Ok(())
 }
 
 #[test]
-#[serial]
-fn adxl_sensor_test() -> TestResult {
-    let reader = Reader::autoconnect()?;
+fn sim_sensor_data() {
+    let mut tag = SimEm4325::new();
+    tag.temperature = 21.5;
+    tag.select(&tag.epc()).unwrap();
 
-    let config = Gen2Cfg::builder().build().unwrap();
+    let bytes = tag.read_alt(MemoryBank::User, 0x100, 2, None).unwrap();
+    assert_eq!(process_temp(u16::from_be_bytes([bytes[0], bytes[1]])), 21.5);
 
-    let mut reader = reader.configure_gen2(&config)?;
+    tag.temperature = -10.25;
+    let bytes = tag.read_alt(MemoryBank::User, 0x100, 2, None).unwrap();
+    assert_eq!(process_temp(u16::from_be_bytes([bytes[0], bytes[1]])), -10.25);
+}
 
-    reader.tune(TuningAlgorithm::Exact)?;
+#[test]
+fn sim_em_write_config() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
+    tag.set_user_word(0xF0, 0xFFFF);
+    tag.set_user_word(0x10D, 0x0001);
 
-    let (_, tags) = reader.inventory_once()?;
+    specific_write_config(&mut tag, epc)?;
 
-    if tags.is_empty() {
-        panic!("No tag found")
-    }
+    assert_eq!(tag.user_word(0xF0), 0x0600);
+    assert_eq!(tag.user_word(0xF2), 0x0001);
+    assert_eq!(tag.user_word(0x10D), 0x0000);
+    Ok(())
+}
 
-    reader.select(&tags[0].epc)?;
+#[test]
+fn sim_em_bap_mode() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
 
-    println!("Charging up semi-BAP");
-    reader.inventory(2000, Box::new(|_| {}))?;
-    reader.inventory_once()?;
+    specific_bap_mode(&mut tag, epc)?;
 
-    println!("Checking ADXL connection...");
+    assert_eq!(tag.user_word(0xF0), 0xE000);
+    assert_eq!(tag.user_word(0xF1), 0x2001);
+    assert_eq!(tag.user_word(0x10D), 0x0001);
+    Ok(())
+}
 
-    assert!(adxl::test_adxl_connection(&mut reader)?);
+#[test]
+fn sim_em_passive_mode() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
 
-    println!("Configuring ADXL...");
+    specific_passive_mode(&mut tag, epc)?;
 
-    adxl::setup(&mut reader)?;
+    assert_eq!(tag.user_word(0xF0), 0xE600);
+    assert_eq!(tag.user_word(0xF2), 0x0000);
+    assert_eq!(tag.user_word(0x10D), 0x0000);
+    Ok(())
+}
 
-    println!("Turning on measurements...");
+#[test]
+fn sim_em_read_config() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
+    specific_read_config(&mut tag, epc)
+}
 
-    adxl::turn_on(&mut reader)?;
+#[test]
+fn sim_em_verify_calibration() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
+
+    // intact calibration is left alone
+    tag.set_user_word(0xEF, 0x5A23);
+    specific_verify_calibration(&mut tag, epc.clone())?;
+    assert_eq!(tag.user_word(0xEF), 0x5A23);
+
+    // corrupted coarse bits are reset from the TID copy
+    tag.set_user_word(0xEF, 0x1234);
+    specific_verify_calibration(&mut tag, epc)?;
+    assert_eq!(tag.user_word(0xEF), 0x5A20);
+    Ok(())
+}
 
-    // wait for 3 measurements to be taken
-    let stime = std::time::Instant::now();
-    let duration = std::time::Duration::from_secs_f32(3.0 / 12.5);
+#[test]
+fn sim_unselected_tag_does_not_respond() {
+    let mut tag = SimEm4325::new();
+    assert!(tag.read_alt(MemoryBank::User, 0xF0, 1, None).is_err());
+    assert!(tag.write(MemoryBank::User, 0xF0, [0x00, 0x00], None).is_err());
+}
 
-    while stime.elapsed() < duration {
-        reader.inventory(20, Box::new(|_| {}))?;
-    }
-    reader.inventory_once()?;
+/// A purple tag on `clock` with its EM4325 selected, so the ADXL363 answers.
+fn selected_purple_tag(clock: &VirtualClock) -> Gen2Result<SimPurpleTag<'_>> {
+    let mut tag = SimPurpleTag::new(SimEm4325::new(), clock);
+    let epc = tag.tag.epc();
+    tag.select(&epc)?;
+    Ok(tag)
+}
 
-    println!("Turning off measurements...");
+#[test]
+fn sim_adxl_ids() -> TestResult {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = SimPurpleTag::new(SimEm4325::new(), &clock);
+
+    // the sensor is only reachable through the selected tag
+    assert!(matches!(tag.connected(), Err(StuhflError::ChipNoResp)));
+    assert!(specific_adxl_setup(&mut tag, HexID::from(vec![0x12; 12])).is_err());
+
+    let epc = tag.tag.epc();
+    tag.select(&epc)?;
+    assert_eq!(tag.read_register(AdxlRegister::DevIdAd, 3)?, ADXL_ID);
+    assert!(tag.connected()?);
+    Ok(())
+}
+
+#[test]
+fn sim_adxl_write_register() -> TestResult {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = selected_purple_tag(&clock)?;
+    let backup = tag.read_register(AdxlRegister::TimeInactL, 2)?;
+
+    tag.write_register(AdxlRegister::TimeInactL, &[0x12, 0x34])?;
+    assert_eq!(tag.read_register(AdxlRegister::TimeInactL, 2)?, [0x12, 0x34]);
+
+    tag.write_register(AdxlRegister::TimeInactL, &backup)?;
+    assert_eq!(tag.read_register(AdxlRegister::TimeInactL, 2)?, backup);
+
+    // setup starts from a soft reset
+    tag.write_register(AdxlRegister::TimeInactL, &[0x12, 0x34])?;
+    tag.setup()?;
+    assert_eq!(tag.read_register(AdxlRegister::TimeInactL, 2)?, [0x00, 0x00]);
+    assert_eq!(tag.read_register(AdxlRegister::FilterCtl, 1)?, [0x10]);
+    Ok(())
+}
 
-    adxl::turn_off(&mut reader)?;
+#[test]
+fn adxl_registers_through_time_inact_burst() -> TestResult {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = selected_purple_tag(&clock)?;
+    tag.write_register(AdxlRegister::TimeInactL, &[0x12, 0x34, 0x05, 0x02])?;
+
+    write_from_time_inact(&mut tag, AdxlRegister::FilterCtl, &[0x11])?;
+    assert_eq!(tag.read_register(AdxlRegister::FilterCtl, 1)?, [0x11]);
+    assert_eq!(read_from_time_inact(&mut tag, AdxlRegister::FilterCtl, 1)?, [0x11]);
+    // the registers in front are written back unchanged
+    assert_eq!(tag.read_register(AdxlRegister::TimeInactL, 4)?, [0x12, 0x34, 0x05, 0x02]);
+
+    write_from_time_inact(&mut tag, AdxlRegister::FifoSamples, &[0x40])?;
+    assert_eq!(read_from_time_inact(&mut tag, AdxlRegister::FifoControl, 2)?, [0x02, 0x40]);
+    Ok(())
+}
 
-    let num_samples = adxl::get_num_fifo_entries(&mut reader)?;
+#[test]
+fn sim_adxl_sensor_flow() -> Result<(), Box<dyn Error>> {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = SimPurpleTag::new(SimEm4325::new(), &clock);
+    let epc = tag.tag.epc();
+
+    // three samples at 12.5 Hz, without the settling words
+    let samples = specific_adxl_sensor(&mut tag, &clock, epc)?;
+    assert_eq!(samples, [[0, 0, 1000]; 3]);
+    assert!(!tag.adxl.measuring());
+    assert_eq!(tag.fifo_entries()?, 0);
+    Ok(())
+}
 
-    println!("Got {num_samples} samples (discarding 3)");
+#[test]
+fn sim_improved_vibration_discards_settling_words() -> Result<(), Box<dyn Error>> {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = SimPurpleTag::new(SimEm4325::new(), &clock);
+    let epc = tag.tag.epc();
+
+    // two field polls take 10 ms, 4 samples at 400 Hz and 2 mg/LSB; the
+    // zeroed settling sample in front of them is dropped
+    let samples = specific_improved_vibration(&mut tag, &clock, epc)?;
+    assert_eq!(samples, [[0, 0, 500]; 4]);
+    assert_eq!(tag.fifo_entries()?, 0);
+    Ok(())
+}
 
-    assert!(num_samples >= 3);
+#[test]
+fn sim_adxl_self_test_deflection() -> TestResult {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = selected_purple_tag(&clock)?;
+    tag.set_measuring(true)?;
+    clock.advance(std::time::Duration::from_millis(100));
+    let data = tag.read_xyz()?;
+
+    tag.set_self_test(true)?;
+    clock.advance(std::time::Duration::from_millis(120));
+    let new_data = tag.read_xyz()?;
+
+    let change: Vec<i16> = new_data.iter().zip(data).map(|(new, old)| new - old).collect();
+    assert_eq!(change, [580, -580, 470]);
+    Ok(())
+}
 
-    println!("Reading {} measurements", (num_samples - 3) / 3);
+#[test]
+fn sim_adxl_over_spi() -> TestResult {
+    let mut adxl = SimAdxl363::new();
+    assert_eq!(adxl_spi_read(&mut adxl, 0x00, 3)?, [0xAD, 0x1D, 0xF3]);
+
+    // bursts run over consecutive registers
+    adxl_spi_write(&mut adxl, 0x25, &[0x12, 0x34])?;
+    assert_eq!(adxl_spi_read(&mut adxl, 0x25, 2)?, [0x12, 0x34]);
+
+    // stream mode at 12.5 Hz: the settling sample, then one real one
+    adxl_spi_write(&mut adxl, 0x28, &[0x02])?;
+    adxl_spi_write(&mut adxl, 0x2C, &[0x10])?;
+    adxl_spi_write(&mut adxl, 0x2D, &[0x02])?;
+    adxl.advance(std::time::Duration::from_millis(100));
+    assert_eq!(adxl_spi_read(&mut adxl, 0x0C, 2)?, [6, 0]);
+    let words = fifo_words(&adxl_spi_read_fifo(&mut adxl, 12)?);
+    assert_eq!(fifo_samples(&words), [[0, 0, 0], [0, 0, 1000]]);
+
+    // the part only decodes mode 0
+    let mode_3 = SpiConfig { mode: SpiMode::Mode3, ..ADXL_SPI };
+    assert_eq!(adxl.spi_transfer(mode_3, &[ADXL_READ_REGISTER, 0x00], 2)?, [0xFF, 0xFF]);
+    let mode_1 = SpiConfig { mode: SpiMode::Mode1, ..ADXL_SPI };
+    let mode_2 = SpiConfig { mode: SpiMode::Mode2, clock_khz: 400 };
+    adxl.spi_write(mode_1, &[ADXL_WRITE_REGISTER, 0x25, 0x00])?;
+    adxl.spi_write(mode_2, &[ADXL_WRITE_REGISTER, 0x25, 0x00])?;
+    assert_eq!(adxl.register_bytes(0x25, 1), [0x12]);
+
+    // nor faster than its maximum SCLK
+    let too_fast = SpiConfig { clock_khz: SimAdxl363::MAX_SCLK_KHZ + 1, ..ADXL_SPI };
+    assert_eq!(adxl.spi_transfer(too_fast, &[ADXL_READ_REGISTER, 0x00], 1)?, [0xFF]);
+    adxl.spi_write(too_fast, &[ADXL_WRITE_REGISTER, 0x25, 0x00])?;
+    assert_eq!(adxl.register_bytes(0x25, 1), [0x12]);
+    let at_max = SpiConfig { clock_khz: SimAdxl363::MAX_SCLK_KHZ, ..ADXL_SPI };
+    assert_eq!(adxl.spi_transfer(at_max, &[ADXL_READ_REGISTER, 0x00], 1)?, [0xAD]);
+    Ok(())
+}
 
-    // discard first 3 samples in fifo
-    for _ in 0..3 {
-        adxl::read_fifo(&mut reader, 2)?;
-    }
+fn numbered_tag(n: u8, temperature: f32) -> SimEm4325 {
+    let mut tag = SimEm4325::with_ids(&[0xE2, 0x00, 0x42, 0x53, 0, 0, 0, 0, 0, 0, 0, n], 0x5A20);
+    tag.temperature = temperature;
+    tag
+}
 
-    // Read all the measurements we got
-    let samples = adxl::get_fifo_entries(&mut reader)?;
-    for (i, sample) in samples.iter().enumerate() {
-        println!("Sample {i}: {sample}")
-    }
+#[test]
+fn sim_field_is_deterministic() -> TestResult {
+    let rounds = |seed| -> Gen2Result<Vec<usize>> {
+        let mut field = SimField::new(seed);
+        for n in 0..5 {
+            field.add(SimFieldTag::new(numbered_tag(n, 20.0), 60, 0.3));
+        }
+        (0..20).map(|_| Ok(field.inventory_once()?.1.len())).collect()
+    };
 
+    let first = rounds(7)?;
+    assert_eq!(first, rounds(7)?);
+    assert!(first.iter().any(|&n| n < 5), "dropouts should thin some rounds");
     Ok(())
 }
 
 #[test]
-#[serial]
-fn adxl_self_test() -> TestResult {
-    let reader = Reader::autoconnect()?;
-
-    let config = Gen2Cfg::builder().build().unwrap();
+fn sim_field_rssi_statistics() -> TestResult {
+    let mut field = SimField::new(1);
+    field.add(SimFieldTag::new(numbered_tag(1, 20.0), 40, 0.0));
+    field.add(SimFieldTag::new(numbered_tag(2, 20.0), 60, 0.0));
 
-    let mut reader = reader.configure_gen2(&config)?;
-
-    reader.tune(TuningAlgorithm::Exact)?;
+    let stats = field.inventory(100, Box::new(|_| {}))?;
+    assert_eq!(stats.rssi_log_mean, 50);
 
-    let (_, tags) = reader.inventory_once()?;
+    let near = SimFieldTag::at_distance(numbered_tag(3, 20.0), 0.5, 0.0);
+    let far = SimFieldTag::at_distance(numbered_tag(4, 20.0), 5.0, 0.0);
+    assert!(near.rssi > far.rssi);
+    Ok(())
+}
 
-    if tags.is_empty() {
-        panic!("No tag found")
+#[test]
+fn sim_field_reads_selected_tag() -> TestResult {
+    let mut field = SimField::new(3);
+    let cold = field.add(SimFieldTag::new(numbered_tag(1, -18.0), 60, 0.0));
+    let warm = field.add(SimFieldTag::new(numbered_tag(2, 4.0), 60, 0.0));
+
+    for (epc, expected) in [(&cold, -18.0), (&warm, 4.0)] {
+        field.select(epc)?;
+        let bytes = field.read_alt(MemoryBank::User, 0x100, 1, None)?;
+        assert_eq!(process_temp(u16::from_be_bytes([bytes[0], bytes[1]])), expected);
     }
+    Ok(())
+}
 
-    reader.select(&tags[0].epc)?;
+#[test]
+fn sim_field_tag_leaves_mid_log() -> TestResult {
+    let mut field = SimField::new(5);
+    let staying = field.add(SimFieldTag::new(numbered_tag(1, 5.0), 60, 0.0));
+    let leaving = field.add(SimFieldTag::new(numbered_tag(2, 5.0), 60, 0.0));
 
-    assert!(adxl::test_adxl_connection(&mut reader)?);
+    assert_eq!(field.inventory_once()?.1.len(), 2);
+    field.select(&leaving)?;
 
-    // Step 1: Read acceleration data for the x-, y-, and x-axes
-    let data = adxl::read_register(&mut reader, adxl::Register::XDataL, 6)?;
+    field.tag_mut(&leaving).unwrap().present = false;
 
-    // Step 2: Assert self test by setting the ST bit in the SELF_TEST register
-    adxl::write_register(&mut reader, adxl::Register::SelfTest, &[0x01])?;
+    let (_, tags) = field.inventory_once()?;
+    assert_eq!(tags.len(), 1);
+    assert!(tags[0].epc == staying);
+    assert!(field.read_alt(MemoryBank::User, 0x100, 1, None).is_err());
+    Ok(())
+}
 
-    // Step 3: Wait 1/ODR for the output to settle to its new value
-    std::thread::sleep(std::time::Duration::from_millis(120));
+#[test]
+fn record_and_replay_session() -> Result<(), Box<dyn Error>> {
+    let capture = std::env::temp_dir().join("record_and_replay_session.capture");
+
+    let mut tag = SimEm4325::new();
+    tag.temperature = 12.75;
+    let epc = tag.epc();
+    tag.set_user_word(0xEF, 0x1234);
+
+    let mut recorder = RecordingReader::create(tag, &capture)?;
+    let (_, tags) = recorder.inventory_once()?;
+    specific_verify_calibration(&mut recorder, tags[0].epc.clone())?;
+    let recorded = recorder.read_alt(MemoryBank::User, 0x100, 2, None)?;
+    let tag = recorder.into_inner();
+    assert_eq!(tag.user_word(0xEF), 0x5A20);
+
+    let mut replay = ReplayReader::open(&capture)?;
+    let (_, tags) = replay.inventory_once()?;
+    assert!(tags[0].epc == epc);
+    specific_verify_calibration(&mut replay, epc)?;
+    assert_eq!(replay.read_alt(MemoryBank::User, 0x100, 2, None)?, recorded);
+    Ok(())
+}
 
-    // Step 4: Read acceleration data for the x-, y- and z-axes. Compare to the values from Step 1, and convert
-    // the difference from LSB to mg by multiplying by the scale actor. If the observed difference falls within
-    // the the self test output change specification listed in Table 2, the device passes self test and is
-    // deemed operational.
-    let new_data = adxl::read_register(&mut reader, adxl::Register::XDataL, 6)?;
+#[test]
+fn replay_reproduces_errors() -> Result<(), Box<dyn Error>> {
+    let capture = std::env::temp_dir().join("replay_reproduces_errors.capture");
+
+    // never selected, so the tag doesn't answer
+    let mut recorder = RecordingReader::create(SimEm4325::new(), &capture)?;
+    assert!(recorder.write(MemoryBank::User, 0x10D, [0x00, 0x01], None).is_err());
+    assert_eq!(recorder.reflected_power(865000)?, (3, -2));
+
+    let mut replay = ReplayReader::open(&capture)?;
+    assert!(matches!(
+        replay.write(MemoryBank::User, 0x10D, [0x00, 0x01], None),
+        Err(StuhflError::ChipNoResp)
+    ));
+    assert_eq!(replay.reflected_power(865000)?, (3, -2));
+    Ok(())
+}
 
-    // TODO: COMPARE VALUES
+#[test]
+fn fault_mid_config_fails_cleanly() {
+    let tag = SimEm4325::new();
+    let epc = tag.epc();
+    // select is call 0, the fourth write is the IO control word
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::Write(3), Fault::Crc);
+
+    assert!(specific_bap_mode(&mut reader, epc).is_err());
+    assert_eq!(reader.injected, 1);
+
+    // the sequence stopped at the failed write
+    let tag = reader.into_inner();
+    assert_eq!(tag.user_word(0xF0), 0x0000);
+    assert_eq!(tag.user_word(0x10D), 0x0000);
+}
 
-    println!("Old value: {:02X?}", &data);
-    println!("New value: {:02X?}", &new_data);
+#[test]
+fn fault_partial_write_sequence() {
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
+    tag.set_user_word(0xF1, 0x2001);
+    tag.set_user_word(0xF2, 0x0001);
+    let mut reader = FaultyReader::new(tag, 1)
+        .with(FaultPoint::Write(4), Fault::LostWriteReply)
+        .with(FaultPoint::WritesFrom(5), Fault::TagNotResponding);
+
+    assert!(specific_passive_mode(&mut reader, epc).is_err());
+
+    // the lost reply still changed the word, nothing after it did
+    let tag = reader.into_inner();
+    assert_eq!(tag.user_word(0xF1), 0x0000);
+    assert_eq!(tag.user_word(0xF0), 0xE600);
+    assert_eq!(tag.user_word(0xF2), 0x0001);
+}
 
-    // Step 5: Deassert self test by clearing hte ST bit in the SELF_TEST register
-    adxl::write_register(&mut reader, adxl::Register::SelfTest, &[0x00])?;
+#[test]
+fn fault_empty_inventory() -> TestResult {
+    let mut reader =
+        FaultyReader::new(SimEm4325::new(), 1).with(FaultPoint::Call(1), Fault::EmptyInventory);
 
+    assert_eq!(reader.inventory_once()?.1.len(), 1);
+    assert!(reader.inventory_once()?.1.is_empty());
+    assert_eq!(reader.inventory_once()?.1.len(), 1);
     Ok(())
 }
 
 #[test]
-#[serial]
-fn improved_vibration() -> TestResult {
-    // Initial setup
-    let reader = Reader::autoconnect()?;
-    let config = Gen2Cfg::builder().build().unwrap();
-    let mut reader = reader.configure_gen2(&config)?;
-
-    // Scanning for tags
-    reader.tune(TuningAlgorithm::Exact)?;
-    let (_, tags) = reader.inventory_once()?;
-    if tags.is_empty() {
-        panic!("No tag found")
-    } else {
-        let epc = &tags[0].epc;
-        println!("Selecting tag with EPC {epc}");
-        reader.select(epc)?;
-    }
+fn write_retry_recovers_or_gives_up() -> TestResult {
+    let mut tag = SimEm4325::new();
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0x0001);
+    tag.select(&tag.epc())?;
+    let mut reader =
+        FaultyReader::new(tag, 42).with(FaultPoint::Probability(0.5), Fault::Timeout);
+
+    write_with_retry(&mut reader, MemoryBank::User, 0x10D, [0x00, 0x01], 50)?;
+
+    let mut reader =
+        FaultyReader::new(reader.into_inner(), 1).with(FaultPoint::WritesFrom(0), Fault::Timeout);
+    let result = write_with_retry(&mut reader, MemoryBank::User, 0x10D, [0x00, 0x00], 10);
+    assert!(matches!(result, Err(StuhflError::Timeout)));
+    assert_eq!(reader.injected, 10);
+    assert_eq!(reader.into_inner().user_word(0x10D), 0x0001);
+    Ok(())
+}
 
-    // Prepare test
-    println!("Charging up semi-BAP");
-    let stats = reader.inventory(2000, Box::new(|_| {}))?;
-    reader.inventory_once()?;
-    let mut peak_rssi = stats.rssi_log_mean;
+#[test]
+fn pseudo_bap_on_virtual_time() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    tag.temperature = 7.5;
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0x0001);
+    tag.select(&tag.epc())?;
+    let clock = VirtualClock::new(chrono::Utc::now());
+
+    let wall = std::time::Instant::now();
+    let measurement = pseudo_bap_measurement(&mut tag, &clock)?;
+
+    assert_eq!(measurement.temperature, 7.5);
+    assert!(clock.elapsed() >= std::time::Duration::from_secs(24));
+    assert!(wall.elapsed() < std::time::Duration::from_secs(1));
+    Ok(())
+}
 
-    println!("Checking ADXL connection...");
-    assert!(adxl::test_adxl_connection(&mut reader)?);
+#[test]
+fn adxl_sample_timestamps() {
+    let clock = VirtualClock::new(chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap());
+    clock.advance(std::time::Duration::from_millis(40));
 
-    println!("Configuring ADXL...");
-    adxl::setup(&mut reader)?;
+    let micros = |odr_hz| -> Vec<u32> {
+        sample_timestamps(clock.now(), odr_hz, 3).iter().map(|t| t.timestamp_subsec_micros()).collect()
+    };
+    assert_eq!(micros(12.5), [120_000, 200_000, 280_000]);
+    // 2.5 ms apart, not truncated to whole milliseconds
+    assert_eq!(micros(400.0), [42_500, 45_000, 47_500]);
+}
 
-    // Actual Reading
-    println!("Turning on measurements...");
-    adxl::turn_on(&mut reader)?;
+#[test]
+fn fifo_samples_follow_axis_tags() {
+    let x = |v: u16| v & 0x3FFF;
+    let y = |v: u16| 1 << 14 | v & 0x3FFF;
+    let z = |v: u16| 2 << 14 | v & 0x3FFF;
+    let temp = 3 << 14 | 350;
+
+    // an overflowed stream FIFO can start mid-sample and end mid-sample
+    let words = [z(9), x(1), y(2), z(0xFFFF), temp, x(4), y(5), z(6), x(7), y(8)];
+    assert_eq!(fifo_samples(&words), [[1, 2, -1], [4, 5, 6]]);
+    // a lost word drops the sample it belonged to
+    assert_eq!(fifo_samples(&[x(1), z(3), x(4), y(5), z(6)]), [[4, 5, 6]]);
+    assert_eq!(fifo_words(&[0x01, 0x40, 0x02]), [0x4001]);
+}
 
-    // wait for 3 measurements to be taken
-    let stime_chrono = chrono::Utc::now();
-    let stime = std::time::Instant::now();
-    let duration = std::time::Duration::from_secs_f32(3.0 / 12.5);
-    while stime.elapsed() < duration {
-        let stats = reader.inventory(20, Box::new(|_| {}))?;
-        peak_rssi = std::cmp::max(stats.rssi_log_mean, peak_rssi);
+#[test]
+fn config_words_round_trip() {
+    for word in [0x0000, 0x0600, 0xE000, 0xE600, 0x2001, 0x0001, 0xFFFF, 0x1234] {
+        assert_eq!(IoControl::from_word(word).to_word(), word);
+        assert_eq!(BatteryManagement1::from_word(word).to_word(), word);
+        assert_eq!(BatteryManagement2::from_word(word).to_word(), word);
+        assert_eq!(TotalWord::from_word(word).to_word(), word);
+        assert_eq!(BapMode::from_word(word).to_word(), word);
+        assert_eq!(TempSensorControl::from_words([word; 3]).to_words(), [word; 3]);
     }
-    // reset Gen2 errors in firmware
-    reader.inventory_once()?;
+}
 
-    // End reading
-    println!("Turning off measurements...");
-    adxl::turn_off(&mut reader)?;
+#[test]
+fn config_words_decode() {
+    let io = IoControl::from_word(0xE600);
+    assert_eq!(io.interface, IoInterface::SpiMasterAux);
+    assert!(io.bap_switch);
+    assert_eq!(io.pin_output_enable, 0b0110);
+    assert!(!IoControl::from_word(0x0600).bap_switch);
+
+    let battery = BatteryManagement1::from_word(0x2001);
+    assert!(battery.bap_allowed && battery.battery_low_detect);
+    assert!(BatteryManagement2::from_word(0x0001).bap_on_command);
+    assert!(BapMode::from_word(0x0001).enabled);
+}
 
-    let num_samples = adxl::get_num_fifo_entries(&mut reader)?;
+#[test]
+fn sim_read_config_decoded() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
+    specific_bap_mode(&mut tag, epc)?;
+
+    let config = Em4325Config::read(&mut tag)?;
+    let printed = config.to_string();
+    assert!(printed.contains("BAP enabled"));
+    assert!(printed.contains("battery mgmt allowed"));
+    assert_eq!(config.io.to_word(), 0xE000);
+    Ok(())
+}
 
-    // the first 3 samples are invalid data created by the setup process
-    println!("Got {num_samples} samples (discarding 3)");
-    assert!(num_samples >= 3);
-    for _ in 0..3 {
-        adxl::read_fifo(&mut reader, 2)?;
+#[test]
+fn sim_apply_profiles() -> Result<(), Box<dyn Error>> {
+    for profile in [
+        Em4325Profile::Default,
+        Em4325Profile::Bap,
+        Em4325Profile::Passive,
+        Em4325Profile::PseudoBap,
+    ] {
+        let mut tag = SimEm4325::new();
+        tag.select(&tag.epc())?;
+        let config = apply_profile(&mut tag, profile)?;
+        assert_eq!(config, profile.config(), "{profile:?}");
     }
 
-    let reflected_i;
-    let reflected_q;
-
-    // Reflected power not implemented in the wrapper
-    unsafe {
-        use libstuhfl_sys as ffi;
+    let passive = Em4325Profile::Passive.steps();
+    assert_eq!(passive.len(), 9);
+    assert_eq!(passive[8].address, BATTERY_MANAGEMENT_WORD_2);
+    assert!(!Em4325Profile::Passive.config().bap_mode.enabled);
+    assert!(Em4325Profile::Bap.config().bap_mode.enabled);
+    Ok(())
+}
 
-        let mut param = ffi::STUHFL_T_ST25RU3993_FreqReflectedPowerInfo {
-            frequency: 865000,
-            applyTunerSetting: true,
-            reflectedI: 0,
-            reflectedQ: 0,
-        };
+#[test]
+fn apply_profile_reports_failed_step() {
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc()).unwrap();
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::Write(4), Fault::Crc);
+
+    let err = apply_config(&mut reader, &Em4325Profile::Bap.steps()).unwrap_err();
+    assert_eq!(err.step, "battery management word 1");
+    assert!(matches!(err.failure, ConfigFailure::Write(StuhflError::ChipCrcError)));
+    assert_eq!(err.written.len(), 4);
+    let state = err.state.unwrap();
+    assert_eq!(state.io.to_word(), 0xE000);
+    assert!(!state.battery_1.bap_allowed);
+}
 
-        let ret_code = ffi::Get_FreqReflectedPower(&mut param);
-        assert!(ret_code == 0); // error handling is normally contained in the wrapper
+#[test]
+fn apply_config_detects_mismatch() {
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc()).unwrap();
+
+    // the tag acknowledges the write but keeps the old word
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::Write(0), Fault::DroppedWrite);
+    let steps = [ConfigStep {
+        name: "IO control word",
+        address: IO_CONTROL_WORD,
+        value: 0x0001,
+    }];
+
+    let err = apply_config(&mut reader, &steps).unwrap_err();
+    assert_eq!(err.step, "IO control word");
+    assert!(matches!(
+        err.failure,
+        ConfigFailure::Mismatch { address: IO_CONTROL_WORD, expected: 0x0001, found: 0x0000 }
+    ));
+    assert_eq!(err.written.len(), 1);
+}
 
-        reflected_i = param.reflectedI;
-        reflected_q = param.reflectedQ;
-    }
+#[test]
+fn transaction_rolls_back_half_configured_tag() -> TestResult {
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc())?;
+    apply_profile(&mut tag, Em4325Profile::Passive).unwrap();
+    let before = Em4325Config::read(&mut tag)?;
+
+    // BAP mode word write fails after battery management was reconfigured
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::Write(7), Fault::LostWriteReply);
+    let err = apply_profile(&mut reader, Em4325Profile::Bap).unwrap_err();
+
+    assert_eq!(err.error.step, "BAP mode word");
+    assert!(matches!(err.rollback, Some(Rollback::Restored)));
+    assert_eq!(err.snapshot.len(), 8);
+    let mut tag = reader.into_inner();
+    assert_eq!(Em4325Config::read(&mut tag)?, before);
+    Ok(())
+}
 
-    println!("Peak RSSI: {peak_rssi}, Reflected Power: {reflected_i} (i) {reflected_q} (q)");
+#[test]
+fn transaction_rolls_back_bap_mode_on_passive_tag() -> TestResult {
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc())?;
+    apply_profile(&mut tag, Em4325Profile::Passive).unwrap();
+
+    // the BAP write lands with its reply lost, leaving BAP on and allowed
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::Write(7), Fault::LostWriteReply);
+    let err = apply_profile(&mut reader, Em4325Profile::Bap).unwrap_err();
+    assert!(matches!(err.rollback, Some(Rollback::Restored)));
+
+    // BAP mode goes back between allowing BAP control and the final BM2
+    let sequence = restore_sequence(&err.snapshot);
+    assert_eq!(
+        sequence[sequence.len() - 3..],
+        [(BATTERY_MANAGEMENT_WORD_2, 0x0001), (BAP_MODE_WORD, 0x0000), (BATTERY_MANAGEMENT_WORD_2, 0x0000)]
+    );
+    let tag = reader.into_inner();
+    assert_eq!(tag.user_word(BAP_MODE_WORD), 0x0000);
+    assert_eq!(tag.user_word(BATTERY_MANAGEMENT_WORD_2), 0x0000);
+    Ok(())
+}
 
-    assert!(num_samples % 3 == 0);
-    println!("Reading {} measurements", (num_samples - 3) / 3);
+#[test]
+fn transaction_reports_failed_rollback() -> TestResult {
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc())?;
+
+    // the tag stops answering writes part-way and never comes back
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::WritesFrom(4), Fault::TagNotResponding);
+    let err = apply_profile(&mut reader, Em4325Profile::Bap).unwrap_err();
+
+    match err.rollback {
+        Some(Rollback::Failed { address, unrestored, .. }) => {
+            assert_eq!(address, TEMP_SENSOR_CONTROL_WORD_1);
+            assert_eq!(unrestored.len(), 8);
+        }
+        other => panic!("unexpected rollback {other:?}"),
+    }
+    assert!(err.to_string().contains("rollback failed"));
+    Ok(())
+}
 
-    // Read all the measurements we got
-    let samples = adxl::get_fifo_entries(&mut reader)?;
+#[test]
+fn transaction_snapshot_failure_writes_nothing() {
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc()).unwrap();
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::Call(2), Fault::Crc);
+
+    let err = apply_profile(&mut reader, Em4325Profile::Bap).unwrap_err();
+    assert_eq!(err.error.step, "snapshot");
+    assert!(err.rollback.is_none());
+    assert_eq!(reader.writes, 0);
+}
 
-    let interval = (1000.0 / 12.5) as i64;
+#[test]
+fn diff_config_names_fields() {
+    let expected = Em4325Profile::Passive.config();
+    let mut found = expected;
+    found.io = IoControl::from_word(0x0600);
+
+    let diffs = diff_config(&expected, &found);
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(
+        diffs[0].to_string(),
+        "IO control: expected 0xE600, found 0x0600 — interface, BAP switch differ"
+    );
+
+    // status bits the chip sets don't count as drift, nor do its counters
+    found = expected;
+    found.battery_2.battery_detected = true;
+    found.total = TotalWord::from_word(12);
+    assert!(diff_config(&expected, &found).is_empty());
+}
 
-    for i in 0..num_samples as usize / 3 {
-        println!(
-            "[{}] {} {} {}",
-            stime_chrono + chrono::Duration::milliseconds(interval * (i as i64 + 1)),
-            samples[i * 3],
-            samples[i * 3 + 1],
-            samples[i * 3 + 2]
-        );
+#[test]
+fn audit_bin_of_tags_without_writing() -> TestResult {
+    let mut field = SimField::new(9);
+    let mut epcs = Vec::new();
+    for n in 0..50 {
+        let mut tag = numbered_tag(n, 20.0);
+        tag.selected = true;
+        apply_profile(&mut tag, Em4325Profile::Passive).unwrap();
+        if n % 10 == 3 {
+            tag.set_user_word(IO_CONTROL_WORD, 0x0600);
+        }
+        tag.selected = false;
+        epcs.push(field.add(SimFieldTag::new(tag, 60, 0.0)));
     }
-    println!("Length of samples: {}", samples.len());
 
-    for (i, sample) in samples.iter().enumerate() {
-        println!("Sample {i}: {sample}");
+    let mut reader = FaultyReader::new(field, 1);
+    let mut drifted = Vec::new();
+    for (n, epc) in epcs.iter().enumerate() {
+        let diffs = audit_tag(&mut reader, epc, Em4325Profile::Passive)?;
+        if !diffs.is_empty() {
+            assert_eq!(diffs[0].address, IO_CONTROL_WORD);
+            drifted.push(n);
+        }
     }
 
+    assert_eq!(drifted, [3, 13, 23, 33, 43]);
+    assert_eq!(reader.writes, 0);
     Ok(())
 }
 
 #[test]
-#[serial]
-fn em_pseudo_bap_mode() -> TestResult {
-    let reader = Reader::autoconnect()?;
-
-    let config = Gen2Cfg::builder().build().unwrap();
+fn snapshot_json_round_trip() -> io::Result<()> {
+    let snapshot = TagSnapshot {
+        epc: "E20042530000000000000001".to_string(),
+        tid: "E200B0040000000000000001".to_string(),
+        taken: "2026-10-18T09:30:00+00:00".to_string(),
+        words: vec![(0xEC, 0x0000), (0xEF, 0x5A23), (0xF0, 0xE600), (0x10D, 0x0001)],
+    };
 
-    let mut reader = reader.configure_gen2(&config)?;
+    assert_eq!(TagSnapshot::from_json(&snapshot.to_json())?, snapshot);
 
-    reader.tune(TuningAlgorithm::Exact)?;
+    let future = snapshot.to_json().replace("\"version\": 1", "\"version\": 2");
+    assert!(TagSnapshot::from_json(&future).is_err());
+    let too_wide = snapshot.to_json().replace("\"value\": 23075", "\"value\": 70000");
+    assert_ne!(too_wide, snapshot.to_json());
+    assert!(TagSnapshot::from_json(&too_wide).is_err());
+    assert!(TagSnapshot::from_json(&snapshot.to_json().replace("\"tid\"", "\"serial\"")).is_err());
+    Ok(())
+}
 
-    let (_, tags) = reader.inventory_once()?;
+#[test]
+fn snapshot_restore_same_and_other_tag() -> Result<(), Box<dyn Error>> {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut field = SimField::new(2);
+    let mut source = numbered_tag(1, 20.0);
+    source.selected = true;
+    apply_profile(&mut source, Em4325Profile::Bap)?;
+    source.set_user_word(CALIBRATION_WORD, 0x5A22);
+    source.selected = false;
+    let source = field.add(SimFieldTag::new(source, 60, 0.0));
+    let mut other = numbered_tag(2, 20.0);
+    other.set_user_word(CALIBRATION_WORD, 0x4421);
+    let other = field.add(SimFieldTag::new(other, 60, 0.0));
+
+    let path = std::env::temp_dir().join("snapshot_restore_same_and_other_tag.json");
+    let snapshot = TagSnapshot::take(&mut field, &clock, &source)?;
+    snapshot.save(&path)?;
+    let snapshot = TagSnapshot::load(&path)?;
+    assert_eq!(snapshot.words.len(), 9);
+
+    // lab experiment leaves the source tag in a different mode
+    field.select(&source)?;
+    apply_profile(&mut field, Em4325Profile::Passive)?;
+    field.write(MemoryBank::User, CALIBRATION_WORD, [0x5A, 0x20], None)?;
+
+    let restored = snapshot.restore(&mut field, &source)?;
+    assert_eq!(restored.config, Em4325Profile::Bap.config());
+    assert!(restored.calibration_restored);
+    assert_eq!(read_word(&mut field, CALIBRATION_WORD)?, 0x5A22);
+
+    let restored = snapshot.restore(&mut field, &other)?;
+    assert_eq!(restored.config, Em4325Profile::Bap.config());
+    assert!(!restored.calibration_restored);
+    assert_eq!(read_word(&mut field, CALIBRATION_WORD)?, 0x4421);
+    Ok(())
+}
 
-    if tags.is_empty() {
-        panic!("No tag found")
+#[test]
+fn calibrate_from_reference_points() -> Result<(), Box<dyn Error>> {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
+    tag.sensor_error = 1.5;
+    tag.select(&epc)?;
+
+    let mut points = Vec::new();
+    for reference in [-20.0, 4.0] {
+        tag.temperature = reference;
+        points.push(measure_calibration_point(&mut tag, &clock, reference)?);
     }
+    assert_eq!(points[0].measured, -18.5);
+
+    let record = calibrate_temperature(&mut tag, &clock, &epc, &points)?;
+    assert_eq!(record.word_before, 0x5A20);
+    assert_eq!(record.word_after, 0x5A3A); // -6 steps of 0.25 °C
+    assert_eq!(record.remeasured, 4.0);
+    assert!(record.confirmed());
+    assert_eq!(tag.user_word(CALIBRATION_WORD), 0x5A3A);
+
+    let log = std::env::temp_dir().join("calibrate_from_reference_points.csv");
+    std::fs::remove_file(&log).ok();
+    record.log(&log)?;
+    let rows = std::fs::read_to_string(&log)?;
+    assert!(rows.lines().nth(1).unwrap().ends_with("0x5A20, 0x5A3A, 4"));
+    Ok(())
+}
 
-    reader.select(&tags[0].epc)?;
-
-    const TEMP_SENSOR_CONTROL_WORD_1: u32 = 0xEC;
-    const TEMP_SENSOR_CONTROL_WORD_2: u32 = 0xED;
-    const TEMP_SENSOR_CONTROL_WORD_3: u32 = 0xEE;
-    const IO_CONTROL_WORD: u32 = 0xF0;
-    const BATTERY_MANAGEMENT_WORD_1: u32 = 0xF1;
-    const BATTERY_MANAGEMENT_WORD_2: u32 = 0xF2;
-    const TOTAL_WORD: u32 = 0xF3;
-    const BAP_MODE_WORD: u32 = 0x10D;
+#[test]
+fn calibrate_rejects_out_of_range_correction() {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
+    tag.sensor_error = -6.0;
+    tag.select(&epc).unwrap();
+
+    let point = measure_calibration_point(&mut tag, &clock, 22.0).unwrap();
+    assert!(calibrate_temperature(&mut tag, &clock, &epc, &[point]).is_err());
+    assert_eq!(tag.user_word(CALIBRATION_WORD), 0x5A20);
+}
 
-    println!("Seting up tag...");
+#[test]
+fn temperature_alarm_limits_encode() {
+    assert_eq!(temp_code(8.0), Some(0x020));
+    assert_eq!(temp_code(-20.25), Some(0x1AF));
+    assert_eq!(process_temp(temp_code(-20.25).unwrap()), -20.25);
+    assert_eq!(temp_code(70.0), None);
+
+    let alarms = TemperatureAlarms {
+        under: Some(-20.25),
+        over: None,
+        sample_interval: 15,
+        alarm_delay: 3,
+    };
+    let blank = TempSensorControl::default();
+    let control = alarms.to_control(&blank).unwrap();
+    assert_eq!(control.to_words(), [0x81AF, 0x0000, 0xB00F]);
+    assert_eq!(TemperatureAlarms::from_control(&control), alarms);
+
+    // reserved bits already on the tag survive the update
+    let current = TempSensorControl::from_words([0x7E00, 0x0200, 0x0F00]);
+    let control = alarms.to_control(&current).unwrap();
+    assert_eq!(control.to_words(), [0xFFAF, 0x0200, 0xBF0F]);
+
+    let inverted = TemperatureAlarms { under: Some(8.0), over: Some(2.0), ..alarms };
+    assert!(inverted.to_control(&blank).is_err());
+    assert!(TemperatureAlarms { alarm_delay: 8, ..alarms }.to_control(&blank).is_err());
+}
 
-    println!("Writing temp sensor control words");
+#[test]
+fn sim_alarm_latches_while_unattended() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
+    tag.select(&epc)?;
+    tag.temperature = 5.0;
+
+    let alarms = TemperatureAlarms {
+        under: Some(2.0),
+        over: Some(8.0),
+        sample_interval: 10,
+        alarm_delay: 2,
+    };
+    assert_eq!(set_temperature_alarms(&mut tag, alarms)?, alarms);
+    assert!(!AlarmState::read(&mut tag)?.triggered());
+
+    // two bad samples are within the delay
+    tag.temperature = 12.0;
+    tag.monitor(20);
+    tag.temperature = 5.0;
+    tag.monitor(10);
+    let state = AlarmState::read(&mut tag)?;
+    assert!(!state.triggered());
+    assert_eq!(state.out_of_limit, 2);
+
+    // the third in a row latches, and it stays latched once back in range
+    tag.temperature = 12.0;
+    tag.monitor(30);
+    tag.temperature = 5.0;
+    let state = AlarmState::read(&mut tag)?;
+    assert_eq!(state, AlarmState { under: false, over: true, out_of_limit: 5 });
+
+    clear_temperature_alarms(&mut tag)?;
+    assert_eq!(AlarmState::read(&mut tag)?, AlarmState::default());
+    Ok(())
+}
 
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
+#[test]
+fn sensor_data_decode() {
+    let data = SensorData::from_words(0x6620, 0x0102);
+    assert_eq!(
+        data,
+        SensorData {
+            temperature: 8.0,
+            valid: true,
+            monitor_active: true,
+            battery_low: false,
+            under_temp_alarm: true,
+            over_temp_alarm: true,
+            aux_event: false,
+            sequence: 0x0102,
+        }
+    );
+    assert_eq!(format!("{data}"), "8 °C (#258, under alarm, over alarm, monitoring)");
 
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_2,
-        [0x00, 0x00],
-        None,
-    )?;
+    let stale = SensorData::from_words(0x9020, 0x0102);
+    assert!(!stale.valid && stale.battery_low && stale.aux_event);
+    assert!(!stale.is_fresh_since(&data));
+}
 
-    reader.write(
-        MemoryBank::User,
-        TEMP_SENSOR_CONTROL_WORD_3,
-        [0x00, 0x00],
-        None,
-    )?;
+#[test]
+fn sim_sensor_data_freshness() -> TestResult {
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc())?;
+
+    let first = read_sensor_data(&mut tag)?;
+    let second = read_sensor_data(&mut tag)?;
+    assert!(first.valid);
+    assert!(second.is_fresh_since(&first));
+    assert!(!first.is_fresh_since(&second));
+    assert!(!second.is_fresh_since(&second));
+
+    // counter wraps
+    let wrapped = SensorData { sequence: 1, ..second };
+    assert!(wrapped.is_fresh_since(&SensorData { sequence: 0xFFFF, ..second }));
+    Ok(())
+}
 
-    println!("Writing IO control word");
+#[test]
+fn pseudo_bap_steps_through_phases() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    tag.temperature = -3.25;
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0x0001);
+    tag.select(&tag.epc())?;
+    let clock = VirtualClock::new(chrono::Utc::now());
+
+    let mut measurement = PseudoBapMeasurement::new(PseudoBapTiming::default());
+    let mut events = Vec::new();
+    let mut phases = Vec::new();
+    while let Some(phase) = measurement.phase() {
+        phases.push(phase);
+        measurement.step(&mut tag, &clock, &mut |event| events.push(event))?;
+    }
+    assert_eq!(
+        phases,
+        [
+            PseudoBapPhase::Discharge,
+            PseudoBapPhase::Charge,
+            PseudoBapPhase::Trigger,
+            PseudoBapPhase::Settle,
+            PseudoBapPhase::Read,
+            PseudoBapPhase::Stop,
+        ]
+    );
+    assert_eq!(measurement.charge_attempts, 1);
+    assert_eq!(measurement.data.unwrap().temperature, -3.25);
+    assert_eq!(events.len(), 13);
+    assert_eq!(events[3], PseudoBapEvent::Charged { after: std::time::Duration::ZERO });
+    assert_eq!(
+        events[1],
+        PseudoBapEvent::Finished(PseudoBapPhase::Discharge, std::time::Duration::from_millis(20500))
+    );
+    Ok(())
+}
 
-    reader.write(MemoryBank::User, IO_CONTROL_WORD, [0x06, 0x00], None)?;
+#[test]
+fn pseudo_bap_charge_times_out() {
+    let mut tag = SimEm4325::new();
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0x0001);
+    let epc = tag.epc();
+    // every write after the discharge goes unanswered
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::WritesFrom(1), Fault::TagNotResponding);
+    reader.select(&epc).unwrap();
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let timing = PseudoBapTiming {
+        charge_retry: std::time::Duration::from_millis(100),
+        phase_timeout: std::time::Duration::from_secs(1),
+        ..Default::default()
+    };
 
-    println!("Writing Battery Management control word");
+    let mut retries = 0;
+    let err = PseudoBapMeasurement::new(timing)
+        .run(&mut reader, &clock, &mut |event| {
+            if let PseudoBapEvent::ChargeRetry { .. } = event {
+                retries += 1;
+            }
+        })
+        .unwrap_err();
+    assert_eq!(err.phase, PseudoBapPhase::Charge);
+    assert!(matches!(err.failure, PseudoBapFailure::Timeout(_)));
+    assert_eq!(retries, 10);
+    assert!(err.to_string().starts_with("pseudo-BAP timed out while charging capacitor"));
+}
 
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_1,
-        [0x00, 0x00],
-        None,
-    )?;
+#[test]
+fn pseudo_bap_discharge_times_out_and_switches_bap_off() {
+    let mut tag = SimEm4325::new();
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0x0001);
+    tag.select(&tag.epc()).unwrap();
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let timing = PseudoBapTiming {
+        phase_timeout: std::time::Duration::from_secs(5),
+        ..Default::default()
+    };
 
-    reader.write(
-        MemoryBank::User,
-        BATTERY_MANAGEMENT_WORD_2,
-        [0x00, 0x01], // allow BAP to be enabled on command
-        None,
-    )?;
+    let err = PseudoBapMeasurement::new(timing).run(&mut tag, &clock, &mut |_| ()).unwrap_err();
+    assert_eq!(err.phase, PseudoBapPhase::Discharge);
+    assert!(matches!(err.failure, PseudoBapFailure::Timeout(after) if after.as_millis() == 20500));
+    // the discharge had switched BAP on
+    assert_eq!(tag.user_word(BAP_MODE_WORD), 0x0000);
+}
 
-    println!("Writing TOTAL word");
+#[test]
+fn pseudo_bap_on_a_schedule() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    tag.temperature = 4.5;
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0x0001);
+    let epc = tag.epc();
+    // the second measurement's trigger write fails
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::Write(7), Fault::Crc);
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let interval = std::time::Duration::from_secs(60);
+
+    let log = std::env::temp_dir().join("pseudo_bap_on_a_schedule.csv");
+    std::fs::remove_file(&log).ok();
+    let samples = log_pseudo_bap(&mut reader, &clock, &epc, PseudoBapTiming::default(), interval, 3, &log)?;
+
+    assert_eq!(samples.len(), 3);
+    assert_eq!(samples[0].result.as_ref().unwrap().temperature, 4.5);
+    assert_eq!(samples[1].result.as_ref().unwrap_err().phase, PseudoBapPhase::Trigger);
+    assert!(samples[2].result.is_ok());
+    assert_eq!((samples[2].timestamp - samples[1].timestamp).num_seconds(), 60);
+
+    let rows = std::fs::read_to_string(&log)?;
+    assert!(rows.lines().nth(2).unwrap().ends_with(", , , failed while initiating measurement"));
+    Ok(())
+}
 
-    reader.write(MemoryBank::User, TOTAL_WORD, [0x00, 0x00], None)?;
+#[test]
+fn lock_payload_bits() {
+    // mask bits 15-14 and action bit 5 for a locked EPC bank
+    assert_eq!(lock_payload(&[(LockTarget::Epc, LockAction::Lock)]), [0x00, 0xC0, 0x20]);
+    assert_eq!(
+        lock_payload(&[
+            (LockTarget::KillPassword, LockAction::PermaLock),
+            (LockTarget::User, LockAction::PermaUnlock),
+        ]),
+        [0x0C, 0x0F, 0x01]
+    );
+    assert_eq!(lock_payload(&[(LockTarget::Tid, LockAction::PermaLock)]), [0x00, 0x30, 0x0C]);
+}
 
-    println!("Writing BAP mode word");
+#[test]
+fn sim_locked_tag_needs_password() -> Result<(), Box<dyn Error>> {
+    let access = [0xCA, 0xFE, 0xBE, 0xEF];
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc())?;
+
+    set_passwords(&mut tag, [0, 0, 0, 1], access, None)?;
+    lock_tag(
+        &mut tag,
+        &[
+            (LockTarget::AccessPassword, LockAction::Lock),
+            (LockTarget::Epc, LockAction::Lock),
+            (LockTarget::User, LockAction::Lock),
+        ],
+        Some(Password::from(access)),
+    )?;
 
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None)?;
+    let state = LockState::probe_with_writes(&mut tag, None)?;
+    assert_eq!(state.kill_password, BankLock::Open);
+    assert_eq!(state.user, Some(BankLock::Locked));
+    assert!(apply_profile(&mut tag, Em4325Profile::Default).is_err());
+    assert!(tag.read_alt(MemoryBank::Reserved, 2, 2, None).is_err());
+
+    // the existing procedures work through the password
+    let mut reader = Authenticated::new(tag, access);
+    assert_eq!(apply_profile(&mut reader, Em4325Profile::Bap)?, Em4325Profile::Bap.config());
+    let mut tag = reader.into_inner();
+
+    lock_tag(&mut tag, &[(LockTarget::Epc, LockAction::PermaLock)], Some(Password::from(access)))?;
+    let state = LockState::probe_with_writes(&mut tag, Some(access))?;
+    assert_eq!(
+        state,
+        LockState {
+            kill_password: BankLock::Open,
+            access_password: BankLock::Locked,
+            epc: Some(BankLock::Permalocked),
+            tid: Some(BankLock::Open),
+            user: Some(BankLock::Locked),
+        }
+    );
+    assert_eq!(
+        state.to_string(),
+        "kill password open, access password locked, EPC permalocked, TID open, user memory locked"
+    );
+
+    // a permalock can't be undone
+    let unlock = [(LockTarget::Epc, LockAction::Unlock)];
+    assert!(lock_tag(&mut tag, &unlock, Some(Password::from(access))).is_err());
+    Ok(())
+}
 
+#[test]
+fn sim_change_locked_access_password() -> Result<(), Box<dyn Error>> {
+    let old = [0xCA, 0xFE, 0xBE, 0xEF];
+    let new = [0x12, 0x34, 0x56, 0x78];
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc())?;
+    set_passwords(&mut tag, [0; 4], old, None)?;
+    lock_tag(&mut tag, &[(LockTarget::AccessPassword, LockAction::Lock)], Some(Password::from(old)))?;
+
+    // the second access password word goes out under the half-updated one
+    set_passwords(&mut tag, [0; 4], new, Some(old))?;
+    assert_eq!(tag.read_alt(MemoryBank::Reserved, 2, 2, Some(Password::from(new)))?, new);
+    assert!(matches!(
+        tag.read_alt(MemoryBank::Reserved, 2, 2, Some(Password::from(old))),
+        Err(StuhflError::ChipNoResp)
+    ));
+
+    // losing the last write leaves a password the error names
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::Write(3), Fault::TagNotResponding);
+    let err = set_passwords(&mut reader, [0; 4], old, Some(new)).unwrap_err();
+    assert!(err.to_string().ends_with("it is now CAFE5678"));
+    let mut tag = reader.into_inner();
+    let half = [0xCA, 0xFE, 0x56, 0x78];
+    assert_eq!(tag.read_alt(MemoryBank::Reserved, 2, 2, Some(Password::from(half)))?, half);
     Ok(())
 }
 
 #[test]
-#[serial]
-fn em_pseudo_bap_test() -> TestResult {
-    let reader = Reader::autoconnect()?;
+fn sim_probe_refuses_unsafe_passwords() -> Result<(), Box<dyn Error>> {
+    let access = [0xCA, 0xFE, 0xBE, 0xEF];
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc())?;
+    set_passwords(&mut tag, [0; 4], access, None)?;
+    lock_tag(&mut tag, &[(LockTarget::User, LockAction::Lock)], Some(Password::from(access)))?;
+
+    // the attempt without a password would still carry it
+    let mut reader = Authenticated::new(tag, access);
+    assert!(LockState::probe(&mut reader, None).is_err());
+
+    // a wrong password is caught before anything is written
+    let mut reader = FaultyReader::new(reader.into_inner(), 1);
+    let err = LockState::probe(&mut reader, Some([0; 4])).unwrap_err();
+    assert_eq!(err.to_string(), "wrong access password");
+    assert_eq!(reader.writes, 0);
+
+    // the plain probe reads only; the banks need the opt-in
+    let state = LockState::probe(&mut reader, Some(access))?;
+    assert_eq!(reader.writes, 0);
+    assert_eq!(state.user, None);
+    assert_eq!(
+        state.to_string(),
+        "kill password open, access password open, EPC not probed, TID not probed, user memory not probed"
+    );
+    assert_eq!(LockState::probe_with_writes(&mut reader, Some(access))?.user, Some(BankLock::Locked));
+    Ok(())
+}
 
-    let config = Gen2Cfg::builder().build().unwrap();
+#[test]
+fn pin_modes_round_trip() {
+    let mut io = IoControl::from_word(0x0600);
+    assert_eq!(io.pin_mode(1), PinMode::Output(false));
+    assert_eq!(io.pin_mode(0), PinMode::Input);
+
+    io.set_pin_mode(0, PinMode::Aux);
+    io.set_pin_mode(2, PinMode::Output(true));
+    io.set_pin_mode(1, PinMode::Input);
+    assert_eq!(io.to_word(), 0x0441);
+    assert_eq!(
+        [0, 1, 2, 3].map(|pin| io.pin_mode(pin)),
+        [PinMode::Aux, PinMode::Input, PinMode::Output(true), PinMode::Input]
+    );
+}
 
-    let mut reader = reader.configure_gen2(&config)?;
+#[test]
+fn sim_door_switch_latches_event() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc())?;
+
+    set_pin_mode(&mut tag, 0, PinMode::Aux)?;
+    set_pin_mode(&mut tag, 3, PinMode::Output(false))?;
+    drive_pin(&mut tag, 3, true)?;
+    assert!(drive_pin(&mut tag, 0, true).is_err());
+    assert_eq!(IoControl::from_word(tag.user_word(IO_CONTROL_WORD)).pin_mode(3), PinMode::Output(true));
+
+    // plain inputs report their level without latching
+    tag.set_pin_input(1, true);
+    let state = IoState::read(&mut tag)?;
+    assert!(state.input(1) && !state.event(1));
+
+    // door opens and closes again while no reader is around
+    tag.set_pin_input(0, true);
+    tag.set_pin_input(0, false);
+    let state = IoState::read(&mut tag)?;
+    assert!(!state.input(0) && state.event(0));
+    assert!(read_sensor_data(&mut tag)?.aux_event);
+
+    clear_pin_events(&mut tag)?;
+    assert_eq!(IoState::read(&mut tag)?, IoState { inputs: 0b0010, events: 0 });
+    assert!(!read_sensor_data(&mut tag)?.aux_event);
+    Ok(())
+}
 
-    reader.tune(TuningAlgorithm::Exact)?;
+#[test]
+fn sim_pins_unavailable_with_spi() -> Result<(), Box<dyn Error>> {
+    let mut tag = SimEm4325::new();
+    tag.select(&tag.epc())?;
+    apply_profile(&mut tag, Em4325Profile::Passive)?;
 
-    let (_, tags) = reader.inventory_once()?;
+    assert!(set_pin_mode(&mut tag, 0, PinMode::Aux).is_err());
+    assert!(set_pin_mode(&mut SimEm4325::new(), 4, PinMode::Input).is_err());
+    Ok(())
+}
 
-    if tags.is_empty() {
-        panic!("No tag found")
+#[test]
+fn simple_sensor_inventory_reads_every_tag() -> Result<(), Box<dyn Error>> {
+    let mut field = SimField::new(5);
+    let epcs: Vec<HexID> = (1..=3)
+        .map(|n| field.add(SimFieldTag::new(numbered_tag(n, 20.0 + n as f32), 200, 0.0)))
+        .collect();
+    for epc in &epcs[..2] {
+        field.select(epc)?;
+        set_simple_sensor_mode(&mut field, true)?;
     }
 
-    reader.select(&tags[0].epc)?;
-
-    const SENSOR_DATA_MSW: u32 = 0x100;
-    // const SENSOR_DATA_LSW: u32 = 0x101;
-    const BAP_MODE_WORD: u32 = 0x10D;
-
-    println!("Discharging capacitor...");
-
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x01], None)?; // enable BAP
-
-    let stime = std::time::Instant::now();
-    let duration = std::time::Duration::from_secs_f32(0.5); // delay & inventory to power-cycle
-    while stime.elapsed() < duration {}
-
-    reader.inventory_once()?;
+    let replies = inventory_sensor_data(&mut field, 12)?;
+    assert_eq!(replies.len(), 3);
+    assert_eq!(replies[0].epc, epcs[0]);
+    assert_eq!(replies[0].data.unwrap().temperature, 21.0);
+    assert_eq!(replies[1].data.unwrap().temperature, 22.0);
+    assert!(replies[1].data.unwrap().valid);
+    assert_eq!(replies[2], SensorReply { epc: epcs[2].clone(), tid: field.tags[2].tag.tid(), data: None });
+
+    // the counter moves on with every round
+    let again = inventory_sensor_data(&mut field, 12)?;
+    assert!(again[0].data.unwrap().is_fresh_since(&replies[0].data.unwrap()));
+
+    field.select(&epcs[0])?;
+    set_simple_sensor_mode(&mut field, false)?;
+    assert!(inventory_sensor_data(&mut field, 12)?[0].data.is_none());
+    Ok(())
+}
 
-    let stime = std::time::Instant::now();
-    let duration = std::time::Duration::from_secs_f32(20.0); // delay again to discharge
-    while stime.elapsed() < duration {}
+#[test]
+fn simple_sensor_round_restores_mode() -> Result<(), Box<dyn Error>> {
+    let mut field = SimField::new(5);
+    let epcs: Vec<HexID> = (1..=3)
+        .map(|n| field.add(SimFieldTag::new(numbered_tag(n, 20.0), 200, 0.0)))
+        .collect();
+    field.select(&epcs[0])?;
+    set_simple_sensor_mode(&mut field, true)?;
+    let xi = |field: &SimField| -> Vec<bool> {
+        field.tags.iter().map(|t| bit(t.tag.pc, PC_XI_BIT)).collect()
+    };
 
-    println!("Charging capacitor...");
+    let replies = simple_sensor_round(&mut field, &epcs, 12)?;
+    assert!(replies.iter().all(|reply| reply.data.is_some()));
+    assert_eq!(xi(&field), [true, false, false]);
 
-    while reader
-        .write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None)
-        .is_err()
-    {}
+    // select, read and write per tag, then the inventory round fails
+    let mut reader = FaultyReader::new(field, 1).with(FaultPoint::Call(9), Fault::Crc);
+    let err = simple_sensor_round(&mut reader, &epcs, 12).unwrap_err();
+    assert!(matches!(err.downcast_ref::<StuhflError>(), Some(StuhflError::ChipCrcError)));
+    assert_eq!(xi(&reader.into_inner()), [true, false, false]);
+    Ok(())
+}
 
-    reader.inventory(200, Box::new(|_| ())).ok();
+#[test]
+fn sensor_replies_without_data_fail_the_check() {
+    let epcs = [numbered_tag(1, 20.0).epc(), numbered_tag(2, 20.0).epc()];
+    let reply = |epc: &HexID, extra: &[u8]| {
+        let mut bytes = hex_bytes(epc);
+        bytes.extend(extra);
+        SensorReply::parse(&InventoryTag { epc: HexID::from(bytes), ..Default::default() }, 12)
+    };
 
-    println!("Initiating a psuedo-bap measurement...");
+    let good = [reply(&epcs[0], &[0x00, 0x50, 0x80, 0x00]), reply(&epcs[1], &[0x00, 0x50, 0x80, 0x00])];
+    assert_eq!(check_sensor_replies(&good, &epcs), Ok(()));
 
-    let stime = std::time::Instant::now();
-    let duration = std::time::Duration::from_secs_f32(0.5); // delay & inventory to power-cycle
-    while stime.elapsed() < duration {}
-    reader.inventory_once()?;
+    // a tag outside the round may answer without data
+    let other = numbered_tag(3, 20.0).epc();
+    assert_eq!(check_sensor_replies(&[reply(&other, &[])], &epcs), Ok(()));
 
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x01], None)?;
+    // XI ignored, or a reply of a different length
+    assert!(check_sensor_replies(&[reply(&epcs[1], &[])], &epcs).is_err());
+    assert!(check_sensor_replies(&[reply(&epcs[0], &[0x00, 0x50])], &epcs).is_err());
+}
 
-    let stime = std::time::Instant::now();
-    let duration = std::time::Duration::from_secs_f32(0.5); // delay & inventory to power-cycle
-    while stime.elapsed() < duration {}
-    reader.inventory_once()?;
+#[test]
+fn temp_log_rows_are_per_tag() -> Result<(), Box<dyn Error>> {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut field = SimField::new(9);
+    let epcs: Vec<HexID> = [(1, 4.0), (2, -18.5), (3, 21.25)]
+        .into_iter()
+        .map(|(n, temperature)| field.add(SimFieldTag::new(numbered_tag(n, temperature), 200, 0.0)))
+        .collect();
+
+    let (_, tags) = field.inventory_once()?;
+    assert_eq!(tags.len(), 3);
+    // the second tag leaves the field between inventory and read
+    field.tag_mut(&epcs[1]).unwrap().present = false;
+
+    let rows = read_tag_temperatures(&mut field, &clock, &tags);
+    assert_eq!(rows[0].temperature, Ok(4.0));
+    assert_eq!(rows[1].temperature, Err("ChipNoResp".to_string()));
+    assert_eq!(rows[2].temperature, Ok(21.25));
+    assert_eq!(rows[1].epc, epcs[1]);
+
+    let dir = std::env::temp_dir().join("temp_log_rows_are_per_tag");
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir)?;
+    append_temperature_log(&clock, &dir, &rows)?;
+    let log = std::fs::read_dir(&dir)?.next().unwrap()?.path();
+    assert!(log.file_name().unwrap().to_string_lossy().starts_with("temperature_status_log "));
+    let lines: Vec<String> = std::fs::read_to_string(log)?.lines().map(String::from).collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].ends_with(", 4, ok"));
+    assert!(lines[2].ends_with(", , failed (ChipNoResp)"));
+    assert!(lines[3].ends_with(", 21.25, ok"));
+    Ok(())
+}
 
-    println!("Allowing field-off effects...");
-    reader.write(MemoryBank::User, SENSOR_DATA_MSW, [0x00, 0x00], None)?;
-    let stime = std::time::Instant::now();
-    let duration = std::time::Duration::from_secs_f32(2.5);
-    while stime.elapsed() < duration {}
+#[test]
+fn battery_health_flags_dying_tags() -> Result<(), Box<dyn Error>> {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let timing = PseudoBapTiming {
+        charge_retry: std::time::Duration::from_millis(100),
+        phase_timeout: std::time::Duration::from_secs(5),
+        ..Default::default()
+    };
 
-    println!("Reading measurement...");
+    let mut tag = SimEm4325::new();
+    let epc = tag.epc();
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0x8001);
+    let health = diagnose_battery(&mut tag, &clock, &epc, timing)?;
+    assert_eq!(health.charge_time, Ok(std::time::Duration::ZERO));
+    assert_eq!(health.problem(), None);
+    assert!(health.to_string().ends_with("charged in 0ns (1 writes) - ok"));
+
+    // 30 charge writes go unanswered: 3 s to charge
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0x8001);
+    let mut reader = FaultyReader::new(tag, 1).with(FaultPoint::Writes(1, 31), Fault::TagNotResponding);
+    let health = diagnose_battery(&mut reader, &clock, &epc, timing)?;
+    assert_eq!(health.charge_attempts, 31);
+    assert_eq!(health.charge_time, Ok(std::time::Duration::from_secs(3)));
+    assert_eq!(health.problem(), Some("slow to charge (3s)".to_string()));
+
+    // never charges
+    let mut reader =
+        FaultyReader::new(reader.into_inner(), 1).with(FaultPoint::WritesFrom(1), Fault::TagNotResponding);
+    let health = diagnose_battery(&mut reader, &clock, &epc, timing)?;
+    assert!(health.problem().unwrap().starts_with("capacitor didn't charge: pseudo-BAP timed out"));
+
+    let mut tag = reader.into_inner();
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0xC001);
+    let health = diagnose_battery(&mut tag, &clock, &epc, timing)?;
+    assert_eq!(health.problem(), Some("battery low, replace it".to_string()));
+    tag.set_user_word(BATTERY_MANAGEMENT_WORD_2, 0x0000);
+    let health = diagnose_battery(&mut tag, &clock, &epc, timing)?;
+    assert_eq!(health.problem(), Some("no battery detected".to_string()));
+    Ok(())
+}
 
-    let measurement = reader.read_alt(MemoryBank::User, SENSOR_DATA_MSW, 2, None)?;
-    let measurement = u16::from_be_bytes([measurement[0], measurement[1]]);
-    let measurement = process_temp(measurement);
+#[test]
+fn battery_report_covers_every_tag() {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut field = SimField::new(2);
+    let epcs: Vec<HexID> = (1..=2)
+        .map(|n| field.add(SimFieldTag::new(numbered_tag(n, 20.0), 200, 0.0)))
+        .collect();
+    let (_, tags) = field.inventory_once().unwrap();
+    field.tag_mut(&epcs[1]).unwrap().present = false;
+
+    let report = battery_health_report(&mut field, &clock, &tags, PseudoBapTiming::default());
+    assert_eq!(report.len(), 2);
+    assert_eq!(report[0].1.as_ref().unwrap().problem(), Some("no battery detected".to_string()));
+    assert!(report[1].1.is_err());
+}
 
-    println!("Stopping measurement...");
+#[test]
+fn adxl_config_registers() {
+    let config = AdxlConfig::builder().build().unwrap();
+    assert_eq!(config.filter_ctl(), 0x13);
+    assert_eq!(config.fifo_control(), 0x00);
+
+    let config = vibration_config();
+    assert_eq!(config.odr.hz(), 400.0);
+    assert_eq!(config.filter_ctl(), 0x45);
+    assert_eq!((config.fifo_control(), config.fifo_samples()), (0x0A, 0xE0));
+    assert_eq!(config.power_ctl(true), 0x02);
+
+    let config = AdxlConfig::builder()
+        .range(AdxlRange::G8)
+        .noise(AdxlNoise::UltraLow)
+        .fifo_mode(FifoMode::Triggered)
+        .fifo_temperature(true)
+        .watermark(128)
+        .build()
+        .unwrap();
+    assert_eq!(config.filter_ctl(), 0x93);
+    assert_eq!(config.fifo_control(), 0x07);
+    assert_eq!(config.power_ctl(false), 0x20);
+
+    let rates = [AdxlOdr::Hz12_5, AdxlOdr::Hz25, AdxlOdr::Hz50, AdxlOdr::Hz100, AdxlOdr::Hz200, AdxlOdr::Hz400];
+    assert_eq!(rates.map(AdxlOdr::hz), [12.5, 25.0, 50.0, 100.0, 200.0, 400.0]);
+
+    let config = AdxlConfig::builder()
+        .noise(AdxlNoise::Low)
+        .fifo_mode(FifoMode::OldestSaved)
+        .watermark(300)
+        .build()
+        .unwrap();
+    assert_eq!((config.fifo_control(), config.fifo_samples()), (0x09, 0x2C));
+    assert_eq!(config.power_ctl(true), 0x12);
+}
 
-    reader.write(MemoryBank::User, BAP_MODE_WORD, [0x00, 0x00], None)?;
+#[test]
+fn adxl_config_rejects_bad_combinations() {
+    let builder = || AdxlConfig::builder().fifo_mode(FifoMode::Stream);
+    assert!(builder().watermark(0).build().is_err());
+    assert!(builder().watermark(512).build().is_err());
+    assert!(builder().watermark(100).build().is_err());
+    assert!(builder().watermark(100).fifo_temperature(true).build().is_ok());
+    assert!(AdxlConfig::builder().fifo_temperature(true).build().is_err());
+}
 
-    let stime = std::time::Instant::now();
-    let duration = std::time::Duration::from_secs_f32(0.5);
-    while stime.elapsed() < duration {}
-    reader.inventory(200, Box::new(|_| ()))?;
+#[test]
+fn sim_adxl_config_rate_and_range() -> TestResult {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = selected_purple_tag(&clock)?;
+    let config = vibration_config();
+    config.write(&mut tag)?;
+    config.set_measuring(&mut tag, true)?;
+
+    // 40 samples in 101 ms at 400 Hz, after the 3 settling words
+    clock.advance(std::time::Duration::from_millis(101));
+    assert_eq!(tag.fifo_entries()?, 3 + 40 * 3);
+
+    // 1 g on Z reads 500 LSB at 2 mg/LSB
+    let [_, _, z] = tag.read_xyz()?;
+    assert_eq!(z as f32 * config.range.scale_mg(), 1000.0);
+    Ok(())
+}
 
-    println!("Got temperature: {measurement} °C");
+#[test]
+fn sim_adxl_self_test_pass_fail() -> TestResult {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = selected_purple_tag(&clock)?;
+    let config = AdxlConfig::builder().build().unwrap();
+    config.set_measuring(&mut tag, true)?;
+
+    let result = self_test(&mut tag, &clock, &config)?;
+    assert!(result.passed());
+    assert_eq!(result.to_string(), "X +580 mg pass, Y -580 mg pass, Z +470 mg pass");
+    assert_eq!(tag.read_register(AdxlRegister::SelfTest, 1)?, [0x00]);
+
+    // a damaged Z axis barely moves; the ±8 g scale is taken into account
+    let config = AdxlConfig::builder().range(AdxlRange::G8).build().unwrap();
+    config.write(&mut tag)?;
+    config.set_measuring(&mut tag, true)?;
+    tag.adxl.self_test_deflection = [580, -580, 120];
+    let result = self_test(&mut tag, &clock, &config)?;
+    assert!(!result.passed());
+    assert_eq!(result.axes[0], AxisSelfTest { change_mg: 580.0, passed: true });
+    assert_eq!(result.axes[2], AxisSelfTest { change_mg: 120.0, passed: false });
+    Ok(())
+}
 
+#[test]
+fn sim_adxl_commissioning() -> Result<(), Box<dyn Error>> {
+    let clock = VirtualClock::new(chrono::Utc::now());
+    let mut tag = selected_purple_tag(&clock)?;
+    assert!(commission_adxl(&mut tag, &clock)?.passed());
+    assert!(!tag.adxl.measuring());
+
+    // switching measurement keeps the noise mode
+    tag.write_register(AdxlRegister::PowerCtl, &[0x10])?;
+    tag.set_measuring(true)?;
+    assert_eq!(tag.read_register(AdxlRegister::PowerCtl, 1)?, [0x12]);
+
+    tag.adxl.registers[SimAdxl363::DEVID_AD] = 0x00;
+    assert!(!tag.connected()?);
+    assert!(commission_adxl(&mut tag, &clock).is_err());
     Ok(())
 }